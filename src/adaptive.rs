@@ -0,0 +1,132 @@
+//! Adaptive parallelism driven by system load and available memory.
+
+use crate::sched::Scheduler;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// Thresholds for adaptive parallelism. Concurrency is halved while
+/// available memory is below `min_available_mb`, reduced by one while the
+/// load average per CPU is above `max_load_per_cpu`, and otherwise raised by
+/// one per sample up to the worker count.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdaptiveConfig {
+    #[serde(default = "default_max_load_per_cpu")]
+    pub max_load_per_cpu: f64,
+    #[serde(default = "default_min_available_mb")]
+    pub min_available_mb: u64,
+    /// Milliseconds between samples.
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+}
+
+fn default_max_load_per_cpu() -> f64 {
+    1.0
+}
+
+fn default_min_available_mb() -> u64 {
+    1024
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        AdaptiveConfig {
+            max_load_per_cpu: default_max_load_per_cpu(),
+            min_available_mb: default_min_available_mb(),
+            interval_ms: default_interval_ms(),
+        }
+    }
+}
+
+/// A snapshot of system pressure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SystemLoad {
+    /// One-minute load average.
+    pub load_avg: f64,
+    pub cpus: usize,
+    pub available_mb: u64,
+}
+
+/// Parses the one-minute load average from `/proc/loadavg`.
+pub fn parse_loadavg(content: &str) -> Option<f64> {
+    content.split_whitespace().next()?.parse().ok()
+}
+
+/// Parses `MemAvailable` from `/proc/meminfo`, in MiB.
+pub fn parse_mem_available_mb(content: &str) -> Option<u64> {
+    content.lines().find_map(|line| {
+        let kb = line.strip_prefix("MemAvailable:")?;
+        let kb: u64 = kb.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kb / 1024)
+    })
+}
+
+/// Reads the current system load, or `None` where `/proc` is unavailable.
+pub fn sample() -> Option<SystemLoad> {
+    #[cfg(target_os = "linux")]
+    {
+        let load_avg = parse_loadavg(&std::fs::read_to_string("/proc/loadavg").ok()?)?;
+        let available_mb = parse_mem_available_mb(&std::fs::read_to_string("/proc/meminfo").ok()?)?;
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Some(SystemLoad {
+            load_avg,
+            cpus,
+            available_mb,
+        })
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Returns the concurrency to use next, between 1 and `max`.
+pub fn next_limit(current: usize, max: usize, load: &SystemLoad, config: &AdaptiveConfig) -> usize {
+    let next = if load.available_mb < config.min_available_mb {
+        current / 2
+    } else if load.load_avg / load.cpus.max(1) as f64 > config.max_load_per_cpu {
+        current.saturating_sub(1)
+    } else {
+        current + 1
+    };
+    next.clamp(1, max.max(1))
+}
+
+/// Returns the concurrency to start with: one command per CPU (up to
+/// `max`), lowered right away if the system is already under pressure.
+pub fn initial_limit(max: usize, load: &SystemLoad, config: &AdaptiveConfig) -> usize {
+    let per_cpu = load.cpus.clamp(1, max.max(1));
+    next_limit(per_cpu, max, load, config).min(per_cpu)
+}
+
+/// Sets the scheduler's first concurrency limit from a system sample, so it
+/// applies before any command starts. Returns the limit, or `None` (leaving
+/// the scheduler unlimited) where the system load cannot be read.
+pub(crate) fn start(scheduler: &Scheduler, max: usize, config: &AdaptiveConfig) -> Option<usize> {
+    let limit = initial_limit(max, &sample()?, config);
+    scheduler.set_limit(limit);
+    Some(limit)
+}
+
+/// Adjusts the scheduler's concurrency, starting from `limit` as set by
+/// [`start`], from system samples every `interval_ms` until `stop` is
+/// closed.
+pub(crate) fn monitor(
+    scheduler: &Scheduler,
+    mut limit: usize,
+    max: usize,
+    config: &AdaptiveConfig,
+    stop: Receiver<()>,
+) {
+    let interval = Duration::from_millis(config.interval_ms.max(1));
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+        if let Some(load) = sample() {
+            limit = next_limit(limit, max, &load, config);
+            scheduler.set_limit(limit);
+        }
+    }
+}
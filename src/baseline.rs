@@ -0,0 +1,179 @@
+//! Baseline diffing: each directory's stdout compared with the stdout of a
+//! chosen baseline directory.
+
+use crate::{CommandResult, DirCommand};
+use anyhow::Result;
+use colored::*;
+use std::path::{Path, PathBuf};
+
+/// Lines of unchanged context around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// Finds the directory named by `baseline` among the commands: an exact
+/// `dir` match, otherwise the only directory with that basename.
+pub fn resolve_baseline(baseline: &str, commands: &[DirCommand]) -> Result<PathBuf> {
+    if let Some(command) = commands.iter().find(|c| c.dir == baseline) {
+        return Ok(PathBuf::from(&command.dir));
+    }
+    let mut matches: Vec<&str> = commands
+        .iter()
+        .map(|c| c.dir.as_str())
+        .filter(|dir| Path::new(dir).file_name().and_then(|n| n.to_str()) == Some(baseline))
+        .collect();
+    matches.dedup();
+    match matches.as_slice() {
+        [dir] => Ok(PathBuf::from(dir)),
+        [] => anyhow::bail!("Baseline directory {baseline} is not one of the directories run"),
+        _ => anyhow::bail!(
+            "Baseline directory {baseline} is ambiguous: {}",
+            matches.join(", ")
+        ),
+    }
+}
+
+/// Renders the diff of every other result's stdout against the baseline
+/// result with the same matrix variant. Results without a baseline to
+/// compare with (skipped, or the baseline didn't run) are left out.
+pub fn render_baseline_diffs(results: &[CommandResult], baseline: &Path) -> String {
+    render_diffs(results, baseline, false)
+}
+
+/// Like [`render_baseline_diffs`], with headers, hunks, removed and added
+/// lines colored for the terminal.
+pub fn render_baseline_diffs_colored(results: &[CommandResult], baseline: &Path) -> String {
+    render_diffs(results, baseline, true)
+}
+
+fn render_diffs(results: &[CommandResult], baseline: &Path, color: bool) -> String {
+    let mut out = String::new();
+    for result in results {
+        if result.directory == baseline || result.skipped {
+            continue;
+        }
+        let Some(base) = results
+            .iter()
+            .find(|r| r.directory == baseline && r.variant == result.variant && !r.skipped)
+        else {
+            continue;
+        };
+        let label = |r: &CommandResult| match &r.variant {
+            Some(variant) => format!("{} [{variant}]", r.directory.display()),
+            None => r.directory.display().to_string(),
+        };
+        let diff = diff_lines(
+            &base.stdout,
+            &result.stdout,
+            &label(base),
+            &label(result),
+            color,
+        );
+        if diff.is_empty() {
+            out.push_str(&format!("= {}: identical to baseline\n", label(result)));
+        } else {
+            out.push_str(&diff);
+        }
+    }
+    out
+}
+
+/// Unified diff of `old` and `new`, or an empty string when their lines
+/// are equal.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    diff_lines(old, new, old_label, new_label, false)
+}
+
+/// Kind of a line in a unified diff, which decides its color.
+#[derive(Clone, Copy)]
+enum LineKind {
+    Header,
+    Hunk,
+    Removed,
+    Added,
+    Context,
+}
+
+/// Appends `line` and a newline to `out`, colored by `kind` if `color`.
+fn push_line(out: &mut String, kind: LineKind, line: &str, color: bool) {
+    if color {
+        let line = match kind {
+            LineKind::Header => line.bold(),
+            LineKind::Hunk => line.cyan(),
+            LineKind::Removed => line.red(),
+            LineKind::Added => line.green(),
+            LineKind::Context => line.normal(),
+        };
+        out.push_str(&format!("{line}\n"));
+    } else {
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+fn diff_lines(old: &str, new: &str, old_label: &str, new_label: &str, color: bool) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff::slice(&old_lines, &new_lines);
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, diff::Result::Both(..)))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Merge changes whose context would overlap into one hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + 1 + CONTEXT_LINES).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = String::new();
+    push_line(
+        &mut out,
+        LineKind::Header,
+        &format!("--- {old_label}"),
+        color,
+    );
+    push_line(
+        &mut out,
+        LineKind::Header,
+        &format!("+++ {new_label}"),
+        color,
+    );
+    for (start, end) in hunks {
+        let counts = |ops: &[diff::Result<&&str>]| {
+            ops.iter().fold((0, 0), |(old, new), op| match op {
+                diff::Result::Left(_) => (old + 1, new),
+                diff::Result::Right(_) => (old, new + 1),
+                diff::Result::Both(..) => (old + 1, new + 1),
+            })
+        };
+        let (old_before, new_before) = counts(&ops[..start]);
+        let (old_count, new_count) = counts(&ops[start..end]);
+        // An empty range starts at the line before it
+        let old_start = old_before + usize::from(old_count > 0);
+        let new_start = new_before + usize::from(new_count > 0);
+        push_line(
+            &mut out,
+            LineKind::Hunk,
+            &format!("@@ -{old_start},{old_count} +{new_start},{new_count} @@"),
+            color,
+        );
+        for op in &ops[start..end] {
+            let (kind, line) = match op {
+                diff::Result::Left(line) => (LineKind::Removed, format!("-{line}")),
+                diff::Result::Right(line) => (LineKind::Added, format!("+{line}")),
+                diff::Result::Both(line, _) => (LineKind::Context, format!(" {line}")),
+            };
+            push_line(&mut out, kind, &line, color);
+        }
+    }
+    out
+}
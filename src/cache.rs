@@ -0,0 +1,342 @@
+//! Result cache keyed by the command, its environment and a fingerprint of
+//! the directory's files, stored under the workspace state directory.
+
+use crate::{
+    history, resolve_alias, should_ignore, CommandResult, DirCommand, JsonCommandResult,
+    LoopConfig, OutputEncoding, StepResult,
+};
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const CACHE_FILE: &str = "cache.json";
+
+/// Excludes loop's state directory from git pathspecs.
+const EXCLUDE_STATE_DIR: &str = ":(exclude).loop";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CacheFile {
+    /// Latest successful result per directory (and matrix variant).
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CacheEntry {
+    key: String,
+    result: JsonCommandResult,
+}
+
+/// The cache for one run: entries loaded from disk plus the keys computed
+/// for each command of the run.
+pub(crate) struct RunCache {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, CacheEntry>>,
+    keys: Vec<OnceLock<Option<String>>>,
+}
+
+impl RunCache {
+    /// Loads the cache from the history directory for a run of `len`
+    /// commands.
+    pub fn load(config: &LoopConfig, len: usize) -> Result<Self> {
+        let dir = history::history_dir(config).ok_or_else(|| {
+            anyhow::anyhow!("The result cache requires root_dir or history_dir to be set")
+        })?;
+        let path = dir.join(CACHE_FILE);
+        let file: CacheFile = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read cache file: {}", path.display()))?;
+            // A corrupt cache only costs a rerun
+            serde_json::from_str(&content).unwrap_or_default()
+        } else {
+            CacheFile::default()
+        };
+        Ok(RunCache {
+            path,
+            entries: Mutex::new(file.entries),
+            keys: (0..len).map(|_| OnceLock::new()).collect(),
+        })
+    }
+
+    /// Returns the cached result of command `index` if its key is
+    /// unchanged. A directory that can't be fingerprinted is never cached.
+    pub fn lookup(
+        &self,
+        index: usize,
+        dir_cmd: &DirCommand,
+        config: &LoopConfig,
+        aliases: &HashMap<String, String>,
+        stdin: Option<&[u8]>,
+    ) -> Option<CommandResult> {
+        let key = self.keys[index]
+            .get_or_init(|| {
+                let command = if dir_cmd.steps.is_empty() {
+                    resolve_alias(&dir_cmd.cmd, aliases)
+                } else {
+                    let steps: Vec<String> = dir_cmd
+                        .steps
+                        .iter()
+                        .map(|step| resolve_alias(&step.cmd, aliases))
+                        .collect();
+                    steps.join("\n")
+                };
+                let dir = Path::new(&dir_cmd.dir);
+                let env = crate::child_env(dir, config, dir_cmd.env.as_ref()).ok()?;
+                cache_key(dir, &command, &env, stdin, config).ok()
+            })
+            .as_ref()?;
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries.get(&slot(dir_cmd)).filter(|e| e.key == *key)?;
+        Some(cached_result(Path::new(&dir_cmd.dir), &entry.result))
+    }
+
+    /// Records the result of command `index` under the key computed by
+    /// [`RunCache::lookup`]. Only successful results are cached.
+    pub fn store(&self, index: usize, dir_cmd: &DirCommand, result: &CommandResult) {
+        let Some(Some(key)) = self.keys[index].get() else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if result.success && !result.skipped {
+            let mut result = JsonCommandResult::from(result);
+            // Spill files are temporary and won't outlive the run
+            result.stdout_file = None;
+            result.stderr_file = None;
+            entries.insert(
+                slot(dir_cmd),
+                CacheEntry {
+                    key: key.clone(),
+                    result,
+                },
+            );
+        } else {
+            entries.remove(&slot(dir_cmd));
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let file = CacheFile {
+            entries: entries.clone(),
+        };
+        fs::write(&self.path, serde_json::to_string_pretty(&file)?)
+            .with_context(|| format!("Failed to write cache file: {}", self.path.display()))
+    }
+}
+
+/// Identifies a command's cache entry: its directory and matrix variant.
+fn slot(dir_cmd: &DirCommand) -> String {
+    match &dir_cmd.variant {
+        Some(variant) => format!("{} [{variant}]", dir_cmd.dir),
+        None => dir_cmd.dir.clone(),
+    }
+}
+
+/// Hashes everything that determines a command's outcome: the resolved
+/// command, its stdin, the directory's files and its environment.
+/// `child_env` is the complete environment the command runs with; of it,
+/// the variables set by `.env` files or the command's env are hashed, as
+/// are the `cache_env` variables, but not the rest of the parent
+/// environment. `clean_env` and `env_allowlist` are hashed too, since they
+/// decide which parent variables the command sees.
+pub fn cache_key(
+    dir: &Path,
+    resolved_command: &str,
+    child_env: &HashMap<String, String>,
+    stdin: Option<&[u8]>,
+    config: &LoopConfig,
+) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hash_field(&mut hasher, resolved_command.as_bytes());
+
+    let mut set: Vec<_> = child_env
+        .iter()
+        .filter(|(name, value)| env::var(name).ok().as_ref() != Some(*value))
+        .collect();
+    set.sort();
+    for (name, value) in set {
+        hash_field(&mut hasher, name.as_bytes());
+        hash_field(&mut hasher, value.as_bytes());
+    }
+    for name in &config.cache_env {
+        hash_field(&mut hasher, name.as_bytes());
+        hash_field(
+            &mut hasher,
+            child_env.get(name).map_or(&[][..], |v| v.as_bytes()),
+        );
+    }
+    hash_field(&mut hasher, &[u8::from(config.clean_env)]);
+    for name in &config.env_allowlist {
+        hash_field(&mut hasher, name.as_bytes());
+    }
+    hash_field(&mut hasher, stdin.unwrap_or_default());
+    hash_field(
+        &mut hasher,
+        input_fingerprint(dir, &config.ignore)?.as_bytes(),
+    );
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Length-prefixes a field so adjacent fields can't run together.
+fn hash_field(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_le_bytes());
+    hasher.update(bytes);
+}
+
+/// Fingerprints the files of `dir`: the git tree hash when the directory is
+/// a clean git checkout, otherwise a hash over its files' contents (git's
+/// tracked and unignored files, or every file not matching `ignore`).
+pub fn input_fingerprint(dir: &Path, ignore: &[String]) -> Result<String> {
+    if let Some(tree) = git_clean_tree(dir) {
+        return Ok(format!("tree:{tree}"));
+    }
+    let files = match git_files(dir) {
+        Some(files) => files,
+        None => {
+            let mut files = Vec::new();
+            walk_files(dir, Path::new(""), ignore, &mut files)?;
+            files.sort();
+            files
+        }
+    };
+    let mut hasher = Sha256::new();
+    for file in files {
+        hash_field(&mut hasher, file.to_string_lossy().as_bytes());
+        match fs::read(dir.join(&file)) {
+            Ok(content) => hash_field(&mut hasher, &content),
+            // Deleted but still tracked
+            Err(_) => hasher.update(b"\xffmissing"),
+        }
+    }
+    Ok(format!("files:{:x}", hasher.finalize()))
+}
+
+fn git(dir: &Path, args: &[&str]) -> Option<Vec<u8>> {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| output.stdout)
+}
+
+/// The tree hash of `HEAD` for `dir`, if it has no local changes.
+fn git_clean_tree(dir: &Path) -> Option<String> {
+    let status = git(
+        dir,
+        &[
+            "status",
+            "--porcelain",
+            "--untracked-files=normal",
+            "--",
+            ".",
+            EXCLUDE_STATE_DIR,
+        ],
+    )?;
+    if !status.is_empty() {
+        return None;
+    }
+    let tree = git(dir, &["rev-parse", "HEAD:./"])?;
+    Some(String::from_utf8_lossy(&tree).trim().to_string())
+}
+
+/// Tracked and untracked-but-not-ignored files under `dir`, sorted.
+fn git_files(dir: &Path) -> Option<Vec<PathBuf>> {
+    let output = git(
+        dir,
+        &[
+            "ls-files",
+            "-z",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+            "--",
+            ".",
+            EXCLUDE_STATE_DIR,
+        ],
+    )?;
+    let mut files: Vec<PathBuf> = output
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| PathBuf::from(String::from_utf8_lossy(name).into_owned()))
+        .collect();
+    files.sort();
+    files.dedup();
+    Some(files)
+}
+
+/// Collects files under `root.join(rel)`, relative to `root`, skipping VCS
+/// and state directories and paths matching `ignore`.
+fn walk_files(root: &Path, rel: &Path, ignore: &[String], files: &mut Vec<PathBuf>) -> Result<()> {
+    let dir = root.join(rel);
+    for entry in fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == ".git" || name == history::STATE_DIR_NAME {
+            continue;
+        }
+        let path = entry.path();
+        if should_ignore(&path, ignore) {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk_files(root, &rel.join(&name), ignore, files)?;
+        } else if file_type.is_file() {
+            files.push(rel.join(&name));
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds a result from its cache entry, marked as cached.
+fn cached_result(dir: &Path, cached: &JsonCommandResult) -> CommandResult {
+    let decode = |text: &str, encoding: Option<OutputEncoding>| match encoding {
+        Some(OutputEncoding::Base64) => BASE64_STANDARD.decode(text).unwrap_or_default(),
+        None => text.as_bytes().to_vec(),
+    };
+    let stdout_bytes = decode(&cached.stdout, cached.stdout_encoding);
+    let stderr_bytes = decode(&cached.stderr, cached.stderr_encoding);
+    CommandResult {
+        success: cached.success,
+        exit_code: cached.exit_code,
+        directory: dir.to_path_buf(),
+        command: cached.command.clone(),
+        stdout: String::from_utf8_lossy(&stdout_bytes).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr_bytes).into_owned(),
+        stdout_bytes,
+        stderr_bytes,
+        duration: Duration::from_millis(cached.duration_ms),
+        steps: cached
+            .steps
+            .iter()
+            .map(|step| StepResult {
+                name: step.name.clone(),
+                success: step.success,
+                exit_code: step.exit_code,
+                duration: Duration::from_millis(step.duration_ms),
+                skipped: step.skipped,
+            })
+            .collect(),
+        variant: cached.variant.clone(),
+        cached: true,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,316 @@
+//! Bounded capture of subprocess output streams.
+
+use crate::redact::Redactor;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::process::{ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Bytes of unterminated output buffered for line-wise redaction before it
+/// is redacted and kept regardless.
+const REDACT_LINE_LIMIT: usize = 64 * 1024;
+
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Output captured from one stream.
+#[derive(Debug, Default)]
+pub(crate) struct CapturedStream {
+    /// The full output, or head and tail around an omission marker when the
+    /// stream exceeded the capture limit.
+    pub bytes: Vec<u8>,
+    /// File holding the complete (redacted) output when the limit was hit.
+    pub spill: Option<PathBuf>,
+}
+
+/// Reads `reader` to the end. With a `limit`, at most `limit` bytes are kept
+/// in memory (the first and last halves); once exceeded, the full stream is
+/// written to a temp file named after `label`. A limited stream is redacted
+/// line by line before it is cut, so secrets spanning a cut are still masked.
+pub(crate) fn capture_stream(
+    mut reader: impl Read,
+    limit: Option<usize>,
+    label: &str,
+    redactor: &Redactor,
+) -> io::Result<CapturedStream> {
+    let Some(limit) = limit else {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        return Ok(CapturedStream { bytes, spill: None });
+    };
+
+    let mut capture = BoundedCapture::new(limit, label);
+    let mut lines = LineRedactor::default();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        capture.push(&lines.push(&buf[..n], redactor));
+    }
+    capture.push(&lines.finish(redactor));
+    Ok(capture.finish())
+}
+
+/// Keeps the head and tail of a stream within a limit, spilling the whole
+/// stream to a temp file once the limit is exceeded.
+struct BoundedCapture<'a> {
+    limit: usize,
+    label: &'a str,
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    total: u64,
+    /// Failing to write the spill file only loses the full output; the head
+    /// and tail are still kept.
+    spill: Option<io::Result<SpillFile>>,
+}
+
+impl<'a> BoundedCapture<'a> {
+    fn new(limit: usize, label: &'a str) -> Self {
+        BoundedCapture {
+            limit,
+            label,
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            total: 0,
+            spill: None,
+        }
+    }
+
+    fn push(&mut self, mut chunk: &[u8]) {
+        self.total += chunk.len() as u64;
+
+        if self.total > self.limit as u64 && self.spill.is_none() {
+            // First overflow: everything seen so far is still in head + tail
+            let (head, tail) = (&self.head, &mut self.tail);
+            self.spill = Some(SpillFile::create(self.label).and_then(|mut file| {
+                file.write(head)?;
+                file.write(tail.make_contiguous())?;
+                Ok(file)
+            }));
+        }
+        if let Some(Ok(file)) = self.spill.as_mut() {
+            if let Err(e) = file.write(chunk) {
+                if let Some(Ok(file)) = self.spill.take() {
+                    file.discard();
+                }
+                self.spill = Some(Err(e));
+            }
+        }
+
+        let head_limit = self.limit / 2;
+        let tail_limit = self.limit - head_limit;
+        if self.head.len() < head_limit {
+            let take = (head_limit - self.head.len()).min(chunk.len());
+            self.head.extend_from_slice(&chunk[..take]);
+            chunk = &chunk[take..];
+        }
+        self.tail.extend(chunk);
+        if self.tail.len() > tail_limit {
+            self.tail.drain(..self.tail.len() - tail_limit);
+        }
+    }
+
+    fn finish(self) -> CapturedStream {
+        let mut head = self.head;
+        let mut tail = Vec::from(self.tail);
+        if self.total > self.limit as u64 {
+            // Keep UTF-8 output valid across the cuts
+            trim_partial_char_end(&mut head);
+            tail.drain(..partial_char_start(&tail));
+        }
+        let omitted = self.total - (head.len() + tail.len()) as u64;
+        let spill = match self.spill.map(|spill| spill.and_then(SpillFile::finish)) {
+            Some(Ok(path)) => Some(path),
+            Some(Err(e)) => {
+                head.extend_from_slice(format!("\n[failed to write full output: {e}]").as_bytes());
+                None
+            }
+            None => None,
+        };
+
+        let mut bytes = head;
+        if omitted > 0 {
+            let location = spill
+                .as_ref()
+                .map(|path| format!(", full output in {}", path.display()))
+                .unwrap_or_default();
+            bytes.extend_from_slice(
+                format!("\n[... {omitted} bytes omitted{location} ...]\n").as_bytes(),
+            );
+        }
+        bytes.extend(tail);
+        CapturedStream { bytes, spill }
+    }
+}
+
+/// Redacts a stream line by line as it is read, so a secret split across
+/// reads is still matched.
+#[derive(Default)]
+struct LineRedactor {
+    pending: Vec<u8>,
+}
+
+impl LineRedactor {
+    /// Returns the redacted output that is complete after `chunk`.
+    fn push<'a>(&mut self, chunk: &'a [u8], redactor: &Redactor) -> Cow<'a, [u8]> {
+        if redactor.is_empty() {
+            return Cow::Borrowed(chunk);
+        }
+        self.pending.extend_from_slice(chunk);
+        let mut redacted = Vec::new();
+        while let Some(pos) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=pos).collect();
+            redacted.extend_from_slice(&redactor.redact_bytes(&line));
+        }
+        if self.pending.len() > REDACT_LINE_LIMIT {
+            redacted.extend_from_slice(&self.finish(redactor));
+        }
+        Cow::Owned(redacted)
+    }
+
+    /// Returns the redacted remainder of an unterminated last line.
+    fn finish(&mut self, redactor: &Redactor) -> Vec<u8> {
+        redactor
+            .redact_bytes(&std::mem::take(&mut self.pending))
+            .into_owned()
+    }
+}
+
+/// Drops a character left incomplete at the end of otherwise valid UTF-8.
+fn trim_partial_char_end(bytes: &mut Vec<u8>) {
+    if let Err(e) = std::str::from_utf8(bytes) {
+        if e.error_len().is_none() {
+            bytes.truncate(e.valid_up_to());
+        }
+    }
+}
+
+/// Returns the number of leading continuation bytes to drop so that `bytes`
+/// starts on a character boundary, if the rest is then valid UTF-8.
+fn partial_char_start(bytes: &[u8]) -> usize {
+    let skip = bytes
+        .iter()
+        .take(3)
+        .take_while(|&&b| b & 0xC0 == 0x80)
+        .count();
+    if skip > 0 && std::str::from_utf8(&bytes[skip..]).is_ok() {
+        skip
+    } else {
+        0
+    }
+}
+
+/// Runs `cmd` with piped stdout/stderr, capturing both concurrently with
+/// [`capture_stream`]. `label` names spill files (e.g. the directory name).
+/// `input` is written to the child's stdin; without it stdin is null.
+pub(crate) fn run_piped(
+    cmd: &mut Command,
+    input: Option<&[u8]>,
+    limit: Option<usize>,
+    label: &str,
+    redactor: &Redactor,
+) -> io::Result<(ExitStatus, CapturedStream, CapturedStream)> {
+    let mut child = cmd
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let (stdout, stderr) = thread::scope(|s| {
+        if let (Some(stdin), Some(input)) = (stdin, input) {
+            s.spawn(move || write_stdin(stdin, input));
+        }
+        let stdout =
+            s.spawn(|| capture_stream(stdout, limit, &format!("{label}-stdout"), redactor));
+        let stderr =
+            s.spawn(|| capture_stream(stderr, limit, &format!("{label}-stderr"), redactor));
+        (
+            stdout.join().expect("stdout capture thread panicked"),
+            stderr.join().expect("stderr capture thread panicked"),
+        )
+    });
+    let status = child.wait()?;
+    Ok((status, stdout?, stderr?))
+}
+
+/// Writes `input` to a child's stdin and closes it. A child that exits or
+/// stops reading early is not an error.
+pub(crate) fn write_stdin(mut stdin: ChildStdin, input: &[u8]) {
+    if let Err(e) = stdin.write_all(input) {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("Failed to write stdin: {e}");
+        }
+    }
+}
+
+/// Temp file receiving the full (already redacted) output of a stream.
+struct SpillFile {
+    file: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl SpillFile {
+    fn create(label: &str) -> io::Result<Self> {
+        let label: String = label
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = env::temp_dir().join(format!(
+            "loop-{}-{}-{label}.log",
+            std::process::id(),
+            SPILL_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&path)?;
+        Ok(SpillFile {
+            file: BufWriter::new(file),
+            path,
+        })
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.file.write_all(chunk)
+    }
+
+    fn finish(mut self) -> io::Result<PathBuf> {
+        match self.file.flush() {
+            Ok(()) => Ok(self.path),
+            Err(e) => {
+                self.discard();
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes an incomplete spill file.
+    fn discard(self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
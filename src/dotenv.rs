@@ -0,0 +1,198 @@
+//! Loading of `.env` files with dotenv syntax.
+//!
+//! Supported syntax:
+//! - `KEY=value`, optionally prefixed with `export `
+//! - `# comments` on their own line, and ` # comments` after unquoted values
+//! - `'single quoted'` values, taken literally
+//! - `"double quoted"` values, with `\n`, `\t`, `\"`, `\\` and `\$` escapes
+//! - quoted values spanning multiple lines
+//! - `$VAR`, `${VAR}` and `${VAR:-default}` interpolation in unquoted and
+//!   double quoted values, resolved against earlier definitions and then the
+//!   parent environment
+
+use crate::LoopConfig;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// File name looked up in the workspace root and in each directory.
+pub const DOTENV_FILE: &str = ".env";
+
+/// Parses dotenv content. `lookup` resolves variables that are referenced
+/// but not defined earlier in the same content.
+pub fn parse_dotenv(
+    content: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<(String, String)>> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let trimmed = trimmed.strip_prefix("export ").unwrap_or(trimmed);
+        let (key, raw_value) = trimmed
+            .split_once('=')
+            .with_context(|| format!("line {}: expected KEY=value", index + 1))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            anyhow::bail!("line {}: invalid variable name '{key}'", index + 1);
+        }
+
+        let resolve = |name: &str| {
+            vars.iter()
+                .rev()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+                .or_else(|| lookup(name))
+        };
+
+        let raw_value = raw_value.trim_start();
+        let value = match raw_value.chars().next() {
+            Some(quote @ ('\'' | '"')) => {
+                // Collect until the closing quote, possibly across lines
+                let mut body = raw_value[1..].to_string();
+                let end = loop {
+                    if let Some(end) = find_closing_quote(&body, quote) {
+                        break end;
+                    }
+                    let (_, next) = lines.next().with_context(|| {
+                        format!("line {}: unterminated {quote} quote", index + 1)
+                    })?;
+                    body.push('\n');
+                    body.push_str(next);
+                };
+                body.truncate(end);
+                if quote == '\'' {
+                    body
+                } else {
+                    expand_value(&body, true, &resolve)
+                }
+            }
+            _ => {
+                let unquoted = match raw_value.find(" #") {
+                    Some(pos) => &raw_value[..pos],
+                    None => raw_value,
+                };
+                expand_value(unquoted.trim_end(), false, &resolve)
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+/// Finds the byte offset of the unescaped closing `quote` in `body`.
+fn find_closing_quote(body: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Applies escapes (when `escapes` is set) and variable interpolation.
+fn expand_value(raw: &str, escapes: bool, resolve: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if escapes => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some(other @ ('"' | '\\' | '$')) => out.push(other),
+                Some(other) => {
+                    out.push('\\');
+                    out.push(other);
+                }
+                None => out.push('\\'),
+            },
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let mut inner = String::new();
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    inner.push(c);
+                }
+                let (name, default) = match inner.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (inner.as_str(), None),
+                };
+                match resolve(name).filter(|v| !v.is_empty() || default.is_none()) {
+                    Some(value) => out.push_str(&value),
+                    None => out.push_str(default.unwrap_or("")),
+                }
+            }
+            '$' if chars
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') =>
+            {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                out.push_str(&resolve(&name).unwrap_or_default());
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Loads a single `.env` file, returning an empty map if it does not exist.
+/// `lookup` resolves variables referenced but not defined in the file.
+pub fn load_dotenv_file(
+    path: &Path,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<HashMap<String, String>> {
+    if !path.is_file() {
+        return Ok(HashMap::new());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read env file: {}", path.display()))?;
+    let vars = parse_dotenv(&content, lookup)
+        .with_context(|| format!("Failed to parse env file: {}", path.display()))?;
+    Ok(vars.into_iter().collect())
+}
+
+/// Returns the variables from the workspace `.env` (under `root_dir`) and the
+/// directory's own `.env`, with the directory file taking precedence.
+/// Interpolation falls back to `inherited`, the environment the subprocess
+/// would otherwise see.
+pub(crate) fn env_for_directory(
+    dir: &Path,
+    config: &LoopConfig,
+    inherited: &HashMap<String, String>,
+) -> Result<HashMap<String, String>> {
+    let mut vars = HashMap::new();
+    if let Some(root) = config.root_dir.as_deref() {
+        vars = load_dotenv_file(&root.join(DOTENV_FILE), &|name| {
+            inherited.get(name).cloned()
+        })?;
+        if dir == root {
+            return Ok(vars);
+        }
+    }
+    let dir_vars = load_dotenv_file(&dir.join(DOTENV_FILE), &|name| {
+        vars.get(name).or_else(|| inherited.get(name)).cloned()
+    })?;
+    vars.extend(dir_vars);
+    Ok(vars)
+}
@@ -0,0 +1,108 @@
+//! Grouped output: directories with identical output are listed together
+//! under a single copy of that output.
+
+use crate::CommandResult;
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Directories whose (normalized) stdout and outcome are identical.
+pub struct OutputGroup<'a> {
+    /// The normalized stdout shared by the group.
+    pub output: String,
+    pub success: bool,
+    pub results: Vec<&'a CommandResult>,
+}
+
+/// Groups `results` by outcome and stdout, largest group first (ties in
+/// first-seen order). Before comparing, matches of the `normalize` regexes
+/// are removed and trailing whitespace is trimmed from each line. Skipped
+/// commands are left out.
+pub fn group_outputs<'a>(
+    results: &'a [CommandResult],
+    normalize: &[String],
+) -> Result<Vec<OutputGroup<'a>>> {
+    let patterns = normalize
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern)
+                .with_context(|| format!("Invalid output normalization pattern: {pattern}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut groups: Vec<OutputGroup> = Vec::new();
+    for result in results.iter().filter(|r| !r.skipped) {
+        let mut output = result.stdout.clone();
+        for pattern in &patterns {
+            output = pattern.replace_all(&output, "").into_owned();
+        }
+        let output = output
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_end()
+            .to_string();
+        match groups
+            .iter_mut()
+            .find(|g| g.success == result.success && g.output == output)
+        {
+            Some(group) => group.results.push(result),
+            None => groups.push(OutputGroup {
+                output,
+                success: result.success,
+                results: vec![result],
+            }),
+        }
+    }
+    // Stable, so equal-sized groups keep first-seen order
+    groups.sort_by_key(|g| std::cmp::Reverse(g.results.len()));
+    Ok(groups)
+}
+
+/// Renders each group as a header listing its directories followed by the
+/// indented output. Groups smaller than the largest one are marked as
+/// outliers and failed groups with `✗`.
+pub fn render_groups(groups: &[OutputGroup]) -> String {
+    let largest = groups.first().map_or(0, |g| g.results.len());
+    let mut out = String::new();
+    for group in groups {
+        let dirs: Vec<String> = group.results.iter().map(|r| label(r)).collect();
+        let count = group.results.len();
+        let noun = if count == 1 {
+            "directory"
+        } else {
+            "directories"
+        };
+        out.push_str(&format!(
+            "{} {count} {noun}: {}",
+            if group.success { "✓" } else { "✗" },
+            dirs.join(", ")
+        ));
+        if groups.len() > 1 && count < largest {
+            out.push_str(" (outlier)");
+        }
+        out.push('\n');
+        if group.output.is_empty() {
+            out.push_str("    (no output)\n");
+        }
+        for line in group.output.lines() {
+            out.push_str(format!("    {line}").trim_end());
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Directory basename, with the matrix variant if any.
+pub(crate) fn label(result: &CommandResult) -> String {
+    let name = result
+        .directory
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(".");
+    match &result.variant {
+        Some(variant) => format!("{name} [{variant}]"),
+        None => name.to_string(),
+    }
+}
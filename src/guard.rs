@@ -0,0 +1,77 @@
+//! Guard rails for destructive commands.
+
+use crate::LoopConfig;
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Patterns that require confirmation when `confirm_dangerous` is set.
+pub const DEFAULT_CONFIRM_PATTERNS: &[&str] = &[
+    // Recursive and forced, as one flag group or separate short/long flags
+    r"(?i)\brm\s+([^;&|]*\s)?(-\w*r\w*f\w*|-\w*f\w*r\w*|(-\w*r\w*|--recursive)\s([^;&|]*\s)?(-\w*f\w*|--force)|(-\w*f\w*|--force)\s([^;&|]*\s)?(-\w*r\w*|--recursive))\b",
+    r"\bgit\s+reset\s+--hard\b",
+    r"\bgit\s+push\b.*(\s--force\b|\s--force-with-lease\b|\s-f\b)",
+    r"\bgit\s+clean\b.*\s-\w*f",
+    r"\bgit\s+branch\b.*\s-D\b",
+    r"\bgit\s+checkout\s+--\s+\.",
+];
+
+/// What to do with a command before running it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuardDecision {
+    Allow,
+    /// Matches a confirm pattern; run only after the user agrees.
+    Confirm(String),
+    /// Matches a deny pattern; never run without `allow_dangerous`.
+    Deny(String),
+}
+
+/// Compiled deny and confirm patterns from the config.
+#[derive(Debug, Default)]
+pub struct CommandGuard {
+    deny: Vec<Regex>,
+    confirm: Vec<Regex>,
+}
+
+impl CommandGuard {
+    /// Builds the guard from `deny_commands` and `confirm_commands`, plus
+    /// `DEFAULT_CONFIRM_PATTERNS` with `confirm_dangerous`. With
+    /// `allow_dangerous` set the guard allows everything.
+    pub fn from_config(config: &LoopConfig) -> Result<Self> {
+        if config.allow_dangerous {
+            return Ok(CommandGuard::default());
+        }
+        let compile = |pattern: &str| {
+            Regex::new(pattern).with_context(|| format!("Invalid command guard pattern: {pattern}"))
+        };
+        let deny = config
+            .deny_commands
+            .iter()
+            .map(|p| compile(p))
+            .collect::<Result<_>>()?;
+        let defaults: &[&str] = if config.confirm_dangerous {
+            DEFAULT_CONFIRM_PATTERNS
+        } else {
+            &[]
+        };
+        let confirm = config
+            .confirm_commands
+            .iter()
+            .map(String::as_str)
+            .chain(defaults.iter().copied())
+            .map(compile)
+            .collect::<Result<_>>()?;
+        Ok(CommandGuard { deny, confirm })
+    }
+
+    /// Checks an alias-resolved command. Deny patterns win over confirm
+    /// patterns.
+    pub fn check(&self, command: &str) -> GuardDecision {
+        if let Some(pattern) = self.deny.iter().find(|p| p.is_match(command)) {
+            return GuardDecision::Deny(pattern.as_str().to_string());
+        }
+        if let Some(pattern) = self.confirm.iter().find(|p| p.is_match(command)) {
+            return GuardDecision::Confirm(pattern.as_str().to_string());
+        }
+        GuardDecision::Allow
+    }
+}
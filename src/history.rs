@@ -0,0 +1,449 @@
+//! Run history stored under the workspace state directory (`.loop/`).
+
+use crate::{
+    CommandResult, DirCommand, JsonCommandResult, JsonSummary, LoopConfig, OutputEncoding,
+};
+use anyhow::{Context, Result};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the per-workspace directory holding loop's state files.
+pub const STATE_DIR_NAME: &str = ".loop";
+
+const LAST_RUN_FILE: &str = "last-run.json";
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// Maximum bytes of stdout/stderr kept per command in the history file.
+/// Output beyond this is truncated from the front, keeping the tail.
+pub const HISTORY_OUTPUT_LIMIT: usize = 4096;
+
+/// Maximum number of runs kept in the history file; older runs are dropped
+/// when a new run is recorded.
+pub const HISTORY_MAX_RUNS: usize = 100;
+
+/// Outcome of a single command from the most recent run.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LastRunEntry {
+    pub directory: String,
+    /// Matrix variant label, when the command ran as part of a matrix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    pub command: String,
+    pub exit_code: i32,
+    pub success: bool,
+}
+
+/// The results of the most recent (non dry-run) execution.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LastRun {
+    pub results: Vec<LastRunEntry>,
+}
+
+impl LastRun {
+    pub fn from_results(results: &[CommandResult]) -> Self {
+        LastRun {
+            results: results
+                .iter()
+                .map(|r| LastRunEntry {
+                    directory: r.directory.display().to_string(),
+                    variant: r.variant.clone(),
+                    command: r.command.clone(),
+                    exit_code: r.exit_code,
+                    success: r.success,
+                })
+                .collect(),
+        }
+    }
+
+    /// Entries that did not succeed.
+    pub fn failures(&self) -> impl Iterator<Item = &LastRunEntry> {
+        self.results.iter().filter(|r| !r.success)
+    }
+}
+
+/// The parts of the run configuration worth keeping alongside its results.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RunConfigSummary {
+    pub parallel: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_parallel: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_filters: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_filters: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_dir: Option<PathBuf>,
+}
+
+impl From<&LoopConfig> for RunConfigSummary {
+    fn from(config: &LoopConfig) -> Self {
+        RunConfigSummary {
+            parallel: config.parallel,
+            max_parallel: config.max_parallel,
+            include_filters: config.include_filters.clone(),
+            exclude_filters: config.exclude_filters.clone(),
+            root_dir: config.root_dir.clone(),
+        }
+    }
+}
+
+/// One recorded run, stored as a single line of the history file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HistoryRun {
+    /// Sequential run number, starting at 1.
+    pub id: u64,
+    /// Seconds since the Unix epoch when the run was recorded.
+    pub timestamp: u64,
+    pub config: RunConfigSummary,
+    pub summary: JsonSummary,
+    pub results: Vec<JsonCommandResult>,
+}
+
+/// A run without its per-command results, as returned by [`list_runs`].
+#[derive(Debug, Clone)]
+pub struct HistoryRunSummary {
+    pub id: u64,
+    pub timestamp: u64,
+    pub config: RunConfigSummary,
+    pub summary: JsonSummary,
+}
+
+/// How a directory's outcome changed between two runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunChange {
+    /// Passed before, fails now.
+    Regressed,
+    /// Failed before, passes now.
+    Fixed,
+    StillFailing,
+    StillPassing,
+    /// Only present in the newer run.
+    Added,
+    /// Only present in the older run.
+    Removed,
+}
+
+/// Per-directory comparison entry produced by [`compare_runs`].
+#[derive(Debug, Clone, Serialize)]
+pub struct RunComparison {
+    pub directory: String,
+    pub change: RunChange,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before_exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after_exit_code: Option<i32>,
+}
+
+/// Returns the directory history files are kept in: `history_dir` when set,
+/// otherwise `.loop/` under `root_dir`. Returns `None` when neither is
+/// configured, in which case nothing is recorded.
+pub fn history_dir(config: &LoopConfig) -> Option<PathBuf> {
+    config
+        .history_dir
+        .clone()
+        .or_else(|| config.root_dir.as_ref().map(|r| r.join(STATE_DIR_NAME)))
+}
+
+/// Records a finished run: overwrites the last-run file and appends the run
+/// to the history file.
+pub fn record_run(dir: &Path, config: &LoopConfig, results: &[CommandResult]) -> Result<()> {
+    save_last_run(dir, results)?;
+    append_run(dir, config, results)?;
+    Ok(())
+}
+
+pub fn save_last_run(dir: &Path, results: &[CommandResult]) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create history directory: {}", dir.display()))?;
+    let path = dir.join(LAST_RUN_FILE);
+    let content = serde_json::to_string_pretty(&LastRun::from_results(results))?;
+    fs::write(&path, content)
+        .with_context(|| format!("Failed to write last run file: {}", path.display()))?;
+    Ok(())
+}
+
+/// Loads the last run from `dir`, or `None` if no run has been recorded yet.
+pub fn load_last_run(dir: &Path) -> Result<Option<LastRun>> {
+    let path = dir.join(LAST_RUN_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read last run file: {}", path.display()))?;
+    let last_run = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse last run file: {}", path.display()))?;
+    Ok(Some(last_run))
+}
+
+/// Appends a run to the history file and returns the stored record.
+pub fn append_run(
+    dir: &Path,
+    config: &LoopConfig,
+    results: &[CommandResult],
+) -> Result<HistoryRun> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create history directory: {}", dir.display()))?;
+
+    let path = dir.join(HISTORY_FILE);
+    let content = if path.exists() {
+        fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read history file: {}", path.display()))?
+    } else {
+        String::new()
+    };
+    let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+    // Only the newest readable run is parsed to number the next one
+    let id = lines
+        .iter()
+        .rev()
+        .find_map(|line| serde_json::from_str::<RunId>(line).ok())
+        .map_or(1, |run| run.id + 1);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let failed = results.iter().filter(|r| !r.success).count();
+    let run = HistoryRun {
+        id,
+        timestamp,
+        config: RunConfigSummary::from(config),
+        summary: JsonSummary {
+            total: results.len(),
+            succeeded: results.len() - failed,
+            failed,
+            skipped: results.iter().filter(|r| r.skipped).count(),
+            cached: results.iter().filter(|r| r.cached).count(),
+            dry_run: config.dry_run,
+        },
+        results: results
+            .iter()
+            .map(|r| {
+                let mut json = JsonCommandResult::from(r);
+                json.stdout = truncate_output(&json.stdout, json.stdout_encoding);
+                json.stderr = truncate_output(&json.stderr, json.stderr_encoding);
+                json
+            })
+            .collect(),
+    };
+
+    let line = serde_json::to_string(&run)?;
+    if lines.len() >= HISTORY_MAX_RUNS {
+        // Rewrite with the newest runs only, replacing the file atomically
+        let mut kept = lines[lines.len() + 1 - HISTORY_MAX_RUNS..].join("\n");
+        kept.push('\n');
+        kept.push_str(&line);
+        kept.push('\n');
+        let tmp = dir.join(format!("{HISTORY_FILE}.tmp"));
+        fs::write(&tmp, kept)
+            .and_then(|()| fs::rename(&tmp, &path))
+            .with_context(|| format!("Failed to write history file: {}", path.display()))?;
+    } else {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open history file: {}", path.display()))?;
+        // Don't continue a partially written last line
+        let separator = if content.is_empty() || content.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        writeln!(file, "{separator}{line}")
+            .with_context(|| format!("Failed to write history file: {}", path.display()))?;
+    }
+    Ok(run)
+}
+
+/// Just the id of a recorded run.
+#[derive(Deserialize)]
+struct RunId {
+    id: u64,
+}
+
+/// Loads every recorded run, oldest first. Lines that can't be parsed (e.g.
+/// a run cut short while being written) are skipped with a warning.
+pub fn load_runs(dir: &Path) -> Result<Vec<HistoryRun>> {
+    let path = dir.join(HISTORY_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read history file: {}", path.display()))?;
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match serde_json::from_str(line) {
+            Ok(run) => Some(run),
+            Err(e) => {
+                eprintln!(
+                    "{} Skipping unreadable run in history file {} line {}: {e}",
+                    "warning:".yellow(),
+                    path.display(),
+                    i + 1
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+/// Returns the most recently recorded duration of each directory, keyed by
+/// directory as recorded. Skipped commands are ignored.
+pub fn last_durations(dir: &Path) -> Result<HashMap<String, Duration>> {
+    let mut durations = HashMap::new();
+    for run in load_runs(dir)? {
+        for result in run.results.iter().filter(|r| !r.skipped) {
+            durations.insert(
+                result.directory.clone(),
+                Duration::from_millis(result.duration_ms),
+            );
+        }
+    }
+    Ok(durations)
+}
+
+/// Lists recorded runs (oldest first) without their per-command results.
+pub fn list_runs(dir: &Path) -> Result<Vec<HistoryRunSummary>> {
+    Ok(load_runs(dir)?
+        .into_iter()
+        .map(|run| HistoryRunSummary {
+            id: run.id,
+            timestamp: run.timestamp,
+            config: run.config,
+            summary: run.summary,
+        })
+        .collect())
+}
+
+/// Returns a single recorded run by id.
+pub fn show_run(dir: &Path, id: u64) -> Result<HistoryRun> {
+    load_runs(dir)?
+        .into_iter()
+        .find(|run| run.id == id)
+        .ok_or_else(|| anyhow::anyhow!("Run #{id} not found in {}", dir.display()))
+}
+
+/// Compares two recorded runs directory-by-directory. Entries are sorted by
+/// directory; a directory that ran several times in one run is judged by its
+/// last result.
+pub fn compare_runs(dir: &Path, before_id: u64, after_id: u64) -> Result<Vec<RunComparison>> {
+    let runs = load_runs(dir)?;
+    let find = |id: u64| {
+        runs.iter()
+            .find(|run| run.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Run #{id} not found in {}", dir.display()))
+    };
+    Ok(compare_results(
+        &find(before_id)?.results,
+        &find(after_id)?.results,
+    ))
+}
+
+fn compare_results(
+    before: &[JsonCommandResult],
+    after: &[JsonCommandResult],
+) -> Vec<RunComparison> {
+    let mut outcomes: BTreeMap<&str, (Option<&JsonCommandResult>, Option<&JsonCommandResult>)> =
+        BTreeMap::new();
+    for r in before {
+        outcomes.entry(&r.directory).or_default().0 = Some(r);
+    }
+    for r in after {
+        outcomes.entry(&r.directory).or_default().1 = Some(r);
+    }
+
+    outcomes
+        .into_iter()
+        .map(|(directory, (before, after))| {
+            let change = match (before.map(|r| r.success), after.map(|r| r.success)) {
+                (Some(true), Some(false)) => RunChange::Regressed,
+                (Some(false), Some(true)) => RunChange::Fixed,
+                (Some(false), Some(false)) => RunChange::StillFailing,
+                (Some(true), Some(true)) => RunChange::StillPassing,
+                (None, _) => RunChange::Added,
+                (_, None) => RunChange::Removed,
+            };
+            RunComparison {
+                directory: directory.to_string(),
+                change,
+                before_exit_code: before.map(|r| r.exit_code),
+                after_exit_code: after.map(|r| r.exit_code),
+            }
+        })
+        .collect()
+}
+
+/// Keeps at most `HISTORY_OUTPUT_LIMIT` bytes from the end of `output`.
+/// Plain text is cut on a char boundary and marked as truncated; base64 is
+/// cut on a 4-character group boundary so it still decodes.
+fn truncate_output(output: &str, encoding: Option<OutputEncoding>) -> String {
+    if output.len() <= HISTORY_OUTPUT_LIMIT {
+        return output.to_string();
+    }
+    let mut start = output.len() - HISTORY_OUTPUT_LIMIT;
+    match encoding {
+        Some(OutputEncoding::Base64) => {
+            start = start.div_ceil(4) * 4;
+            output[start..].to_string()
+        }
+        None => {
+            while !output.is_char_boundary(start) {
+                start += 1;
+            }
+            format!("[... truncated ...]\n{}", &output[start..])
+        }
+    }
+}
+
+fn require_last_run(config: &LoopConfig) -> Result<LastRun> {
+    let dir = history_dir(config).ok_or_else(|| {
+        anyhow::anyhow!("Rerunning failures requires `root_dir` or `history_dir` to be set")
+    })?;
+    load_last_run(&dir)?
+        .ok_or_else(|| anyhow::anyhow!("No previous run recorded in {}", dir.display()))
+}
+
+/// Builds a command list from the failures of the last recorded run, using
+/// the recorded (alias-resolved) command for each directory and the
+/// configured env. Include/exclude filters from `config` are applied.
+/// Commands are recorded after redaction, so a command containing a
+/// redacted secret cannot be replayed from here; use `rerun_failed` instead.
+/// Pipeline steps and matrix variants are not kept either: a pipeline is
+/// replayed as its `&&`-joined steps, and a failed variant without its
+/// env.
+pub fn last_run_failures(config: &LoopConfig) -> Result<Vec<DirCommand>> {
+    let last_run = require_last_run(config)?;
+    let commands = last_run
+        .failures()
+        .map(|entry| DirCommand {
+            dir: entry.directory.clone(),
+            cmd: entry.command.clone(),
+            env: config.env.clone(),
+            ..Default::default()
+        })
+        .collect();
+    Ok(crate::filter_commands(config, commands))
+}
+
+/// Keeps only the commands whose directory and matrix variant failed in the
+/// last recorded run.
+pub(crate) fn retain_last_failures(
+    config: &LoopConfig,
+    commands: &mut Vec<DirCommand>,
+) -> Result<()> {
+    let last_run = require_last_run(config)?;
+    commands.retain(|c| {
+        last_run
+            .failures()
+            .any(|f| f.directory == c.dir && f.variant == c.variant)
+    });
+    Ok(())
+}
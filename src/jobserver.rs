@@ -0,0 +1,222 @@
+//! GNU make jobserver support, bounding the jobs started by all commands
+//! together. Unix only.
+
+use crate::LoopConfig;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Byte written to the pipe for each free job slot.
+#[cfg(unix)]
+const TOKEN: u8 = b'+';
+
+/// A jobserver shared with every subprocess through `MAKEFLAGS`.
+pub(crate) struct Jobserver {
+    read: File,
+    write: File,
+    makeflags: String,
+    /// Loop, like any jobserver client, owns one slot without a token.
+    implicit_free: AtomicBool,
+}
+
+/// A job slot held while one command runs; released on drop.
+pub(crate) struct Token<'a> {
+    jobserver: &'a Jobserver,
+    byte: Option<u8>,
+}
+
+impl Jobserver {
+    /// Returns the jobserver for this run when `config.jobserver` is set: the
+    /// one named in an inherited `MAKEFLAGS` (loop itself runs under make),
+    /// or a new one with `jobserver_slots` slots.
+    pub fn for_config(config: &LoopConfig) -> Result<Option<Self>> {
+        if !config.jobserver {
+            return Ok(None);
+        }
+        let inherited = std::env::var("MAKEFLAGS").ok();
+        if let Some(jobserver) = inherited.as_deref().and_then(Jobserver::from_makeflags) {
+            return Ok(Some(jobserver));
+        }
+        let slots = config
+            .jobserver_slots
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+        if slots == 0 {
+            anyhow::bail!("jobserver_slots must be at least 1");
+        }
+        Ok(Some(Jobserver::create(slots, inherited.as_deref())?))
+    }
+
+    /// Connects to the jobserver named by `--jobserver-auth` (or the older
+    /// `--jobserver-fds`) in `makeflags`, if its descriptors or fifo are
+    /// usable.
+    pub fn from_makeflags(makeflags: &str) -> Option<Self> {
+        let auth = makeflags
+            .split_whitespace()
+            .filter_map(|flag| {
+                flag.strip_prefix("--jobserver-auth=")
+                    .or_else(|| flag.strip_prefix("--jobserver-fds="))
+            })
+            .next_back()?;
+        let (read, write) = open_auth(auth)?;
+        Some(Jobserver {
+            read,
+            write,
+            makeflags: makeflags.to_string(),
+            implicit_free: AtomicBool::new(true),
+        })
+    }
+
+    /// Creates a jobserver with `slots` job slots. `makeflags` holds other
+    /// inherited make flags to keep.
+    #[cfg(unix)]
+    pub fn create(slots: usize, makeflags: Option<&str>) -> io::Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        let mut fds = [-1; 2];
+        // SAFETY: pipe fills in two new descriptors on success. They are
+        // left inheritable so every subprocess can reach the jobserver.
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: both descriptors were just opened and are owned by us
+        let (read, mut write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        write.write_all(&vec![TOKEN; slots - 1])?;
+
+        let own = format!(
+            "-j{slots} --jobserver-fds={r},{w} --jobserver-auth={r},{w}",
+            r = fds[0],
+            w = fds[1]
+        );
+        let makeflags = match makeflags.map(str::trim).filter(|f| !f.is_empty()) {
+            Some(other) => format!("{other} {own}"),
+            None => own,
+        };
+        Ok(Jobserver {
+            read,
+            write,
+            makeflags,
+            implicit_free: AtomicBool::new(true),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn create(_slots: usize, _makeflags: Option<&str>) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the jobserver is only supported on Unix",
+        ))
+    }
+
+    /// `MAKEFLAGS` value that gives subprocesses access to this jobserver.
+    pub fn makeflags(&self) -> &str {
+        &self.makeflags
+    }
+
+    /// Blocks until a job slot is free.
+    pub fn acquire(&self) -> io::Result<Token<'_>> {
+        if self.implicit_free.swap(false, Ordering::SeqCst) {
+            return Ok(Token {
+                jobserver: self,
+                byte: None,
+            });
+        }
+        let mut byte = [0u8; 1];
+        loop {
+            wait_readable(&self.read)?;
+            match (&self.read).read(&mut byte) {
+                Ok(1) => {
+                    return Ok(Token {
+                        jobserver: self,
+                        byte: Some(byte[0]),
+                    })
+                }
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "jobserver pipe closed",
+                    ))
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                    ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Token<'_> {
+    fn drop(&mut self) {
+        match self.byte {
+            None => self.jobserver.implicit_free.store(true, Ordering::SeqCst),
+            Some(byte) => {
+                // Give the slot back; a lost token only lowers parallelism
+                let _ = (&self.jobserver.write).write_all(&[byte]);
+            }
+        }
+    }
+}
+
+/// Opens the read and write ends named by a `--jobserver-auth` value:
+/// `R,W` descriptor numbers or `fifo:PATH`.
+#[cfg(unix)]
+fn open_auth(auth: &str) -> Option<(File, File)> {
+    use std::fs::OpenOptions;
+    use std::os::fd::{BorrowedFd, OwnedFd};
+
+    if let Some(path) = auth.strip_prefix("fifo:") {
+        let read = OpenOptions::new().read(true).write(true).open(path).ok()?;
+        let write = read.try_clone().ok()?;
+        return Some((read, write));
+    }
+    let (r, w) = auth.split_once(',')?;
+    let (r, w): (i32, i32) = (r.parse().ok()?, w.parse().ok()?);
+    if r < 0 || w < 0 {
+        return None;
+    }
+    // SAFETY: F_GETFD only checks that the descriptor is open
+    if unsafe { libc::fcntl(r, libc::F_GETFD) == -1 || libc::fcntl(w, libc::F_GETFD) == -1 } {
+        // make didn't pass the descriptors on (recipe not marked with `+`)
+        return None;
+    }
+    // Duplicate rather than take ownership; the inherited descriptors must
+    // stay open for our own subprocesses
+    // SAFETY: both descriptors were checked to be open above
+    let dup = |fd| unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned();
+    let (read, write): (OwnedFd, OwnedFd) = (dup(r).ok()?, dup(w).ok()?);
+    Some((File::from(read), File::from(write)))
+}
+
+#[cfg(not(unix))]
+fn open_auth(_auth: &str) -> Option<(File, File)> {
+    None
+}
+
+/// Waits until `file` has data, so non-blocking inherited pipes behave like
+/// blocking ones.
+#[cfg(unix)]
+fn wait_readable(file: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let mut pollfd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: polls a single descriptor we own
+    if unsafe { libc::poll(&mut pollfd, 1, -1) } == -1 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn wait_readable(_file: &File) -> io::Result<()> {
+    Ok(())
+}
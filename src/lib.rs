@@ -1,9 +1,10 @@
 use anyhow::{Context, Result};
+use base64::prelude::*;
 use colored::*;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
@@ -11,8 +12,33 @@ use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub mod adaptive;
+pub mod baseline;
+mod cache;
+mod capture;
+pub mod dotenv;
+pub mod group;
+pub mod guard;
+pub mod history;
+mod jobserver;
+pub mod matrix;
+#[cfg(target_os = "linux")]
+mod pty;
+pub mod redact;
+pub mod report;
+mod sched;
+mod table;
+mod watch;
+
+use adaptive::AdaptiveConfig;
+use cache::RunCache;
+use guard::{CommandGuard, GuardDecision};
+use jobserver::Jobserver;
+use redact::Redactor;
+use sched::{schedule_order, Scheduler};
 
 /// Returns the shell program and command-line flag for executing commands.
 /// On Unix: uses $SHELL or /bin/sh with -c
@@ -75,16 +101,290 @@ pub struct LoopConfig {
     /// and is sorted first in output.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub root_dir: Option<PathBuf>,
+    /// Directory where run history is recorded. Defaults to `.loop/` under
+    /// `root_dir`; when neither is set, runs are not recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_dir: Option<PathBuf>,
+    /// Only run commands in directories that failed in the last recorded run.
+    /// Composes with include/exclude filters.
+    #[serde(default)]
+    pub rerun_failed: bool,
+    /// Load `.env` files (dotenv syntax) from `root_dir` and from each
+    /// directory. Precedence, lowest to highest: parent environment,
+    /// workspace `.env`, directory `.env`, then `env` / `DirCommand.env`.
+    #[serde(default)]
+    pub load_env_files: bool,
+    /// Start subprocesses from an empty environment containing only the
+    /// variables in `DEFAULT_ENV_ALLOWLIST` and `env_allowlist`, plus the
+    /// configured env. `.env` interpolation only sees the allowed variables.
+    #[serde(default)]
+    pub clean_env: bool,
+    /// Additional parent environment variables to pass through when
+    /// `clean_env` is set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_allowlist: Vec<String>,
+    /// Names of environment variables whose values are masked in captured
+    /// output, displayed commands, JSON results and run history.
+    /// Output of sequential (non-capturing) runs goes straight to the
+    /// terminal and is not redacted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_env: Vec<String>,
+    /// Regex patterns whose matches are masked like `redact_env` values.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_patterns: Vec<String>,
+    /// Maximum bytes of stdout and of stderr kept in memory per captured
+    /// command. Beyond this only the first and last halves are kept and the
+    /// complete stream is written to a temp file reported in the result.
+    /// Unlimited by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_limit: Option<usize>,
+    /// Run captured (parallel or JSON) commands under their own pseudo-terminal
+    /// so they behave as in an interactive terminal. stdout and stderr are
+    /// merged, as in a terminal, and reported as stdout. Linux only; ignored
+    /// on other platforms.
+    #[serde(default)]
+    pub pty: bool,
+    /// Input written to the stdin of every command. Each command receives
+    /// the full input; without it, sequential commands inherit stdin and
+    /// captured commands get an empty stdin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<StdinSource>,
+    /// Ask before running the command in each directory (run, skip, run
+    /// all remaining, or quit). Forces sequential execution, requires a
+    /// terminal on stdin and is ignored for dry runs.
+    #[serde(default)]
+    pub interactive: bool,
+    /// Regex patterns for commands that are never run. Matched against the
+    /// alias-resolved command.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_commands: Vec<String>,
+    /// Regex patterns for commands that need confirmation from a terminal
+    /// before running; without one the run is refused.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub confirm_commands: Vec<String>,
+    /// Also require confirmation for the commands matching
+    /// `guard::DEFAULT_CONFIRM_PATTERNS` (`rm -rf`, `git reset --hard`,
+    /// `git push --force`, ...). Off by default.
+    #[serde(default)]
+    pub confirm_dangerous: bool,
+    /// Run commands matching `deny_commands` or `confirm_commands` without
+    /// asking.
+    #[serde(default)]
+    pub allow_dangerous: bool,
+    /// Maximum number of parallel commands holding each resource key (see
+    /// `DirCommand.resources`), e.g. `{"github.com": 4}`. Applies in
+    /// addition to `max_parallel`; keys without a limit are unconstrained.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub resource_limits: HashMap<String, usize>,
+    /// Adapt the number of concurrent parallel commands to CPU load and
+    /// available memory, between 1 and the pool size. Reads `/proc`, so it
+    /// only takes effect on Linux.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<AdaptiveConfig>,
+    /// Act as a GNU make jobserver so `make`, `cargo` and other jobserver
+    /// clients share one pool of job slots across all commands. An inherited
+    /// jobserver (`--jobserver-auth` in `MAKEFLAGS`) is used when available.
+    /// Unix only.
+    #[serde(default)]
+    pub jobserver: bool,
+    /// Job slots of a jobserver created by loop. Defaults to the CPU count.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jobserver_slots: Option<usize>,
+    /// Order in which parallel commands are started. Results are still
+    /// displayed sorted by directory.
+    #[serde(default)]
+    pub schedule: ScheduleStrategy,
+    /// Seed for `ScheduleStrategy::Shuffle`, to reproduce an order. Random
+    /// when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shuffle_seed: Option<u64>,
+    /// Keep running the remaining steps of a pipeline after a step fails.
+    /// By default later steps are skipped.
+    #[serde(default)]
+    pub continue_on_step_failure: bool,
+    /// After running, keep watching the directories and rerun the command
+    /// in those whose files change, until interrupted. Linux only. Changes
+    /// made during a run trigger the next cycle, so list directories the
+    /// commands write to (build output) in `watch_ignore`.
+    #[serde(default)]
+    pub watch: bool,
+    /// Paths not watched in watch mode, in addition to `ignore`. Unlike
+    /// `ignore`, these don't change which directories are expanded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub watch_ignore: Vec<String>,
+    /// Quiet period in milliseconds after a change before a watch cycle
+    /// starts, so a burst of saves triggers one rerun.
+    #[serde(default = "default_watch_debounce_ms")]
+    pub watch_debounce_ms: u64,
+    /// Skip directories whose command, env, stdin and files are unchanged
+    /// since their last successful run, reporting that result as cached.
+    /// Files are fingerprinted by the git tree hash of a clean checkout,
+    /// otherwise by content. Needs a history directory.
+    #[serde(default)]
+    pub cache: bool,
+    /// Parent environment variables that also invalidate the cache when
+    /// their value changes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cache_env: Vec<String>,
+    /// Instead of one output block per directory, print each distinct
+    /// stdout once with the directories that produced it, marking the
+    /// directories that differ from the majority. Output is captured.
+    #[serde(default)]
+    pub group_output: bool,
+    /// Regex patterns removed from stdout before grouping, e.g. timestamps
+    /// or paths that differ between otherwise identical outputs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_normalize: Vec<String>,
+    /// Directory whose stdout every other directory's stdout is diffed
+    /// against, printed as unified diffs instead of per-directory output.
+    /// Matches a directory exactly or by its unique basename. Output is
+    /// captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff_baseline: Option<String>,
+    /// Summarize the run as a table with one row per directory (status,
+    /// exit code, duration and an output excerpt) instead of per-directory
+    /// output. Rows are fitted to the terminal width and colored on a
+    /// terminal, and printed in full otherwise. Output is captured.
+    #[serde(default)]
+    pub table_output: bool,
+    /// Regex selecting the output excerpt shown in the table (its first
+    /// capture group if it has one). Defaults to the first non-empty line
+    /// of stdout, or of stderr.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table_extract: Option<String>,
+    /// Write a JUnit XML report of each run to this file, with one test
+    /// case per directory, or to stdout when set to `-`. Not written for
+    /// dry runs. While any report is enabled, commands run one at a time
+    /// have their output captured and printed once each finishes, rather
+    /// than streamed. A report written to stdout (`-`) implies `silent`, so
+    /// nothing else is printed there; only one report can go to stdout, and
+    /// not together with `json_output`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub junit_report: Option<PathBuf>,
+    /// Write a TAP version 13 report of each run to this file, or to stdout
+    /// when set to `-`. Not written for dry runs. Captures output like
+    /// `junit_report`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap_report: Option<PathBuf>,
+    /// Write a Markdown report of each run (summary table and collapsed
+    /// output of failures) to this file, or to stdout when set to `-`. Not
+    /// written for dry runs. Captures output like `junit_report`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub markdown_report: Option<PathBuf>,
+    /// Write a self-contained HTML report of each run (filterable list of
+    /// directories with expandable, colored output) to this file, or to
+    /// stdout when set to `-`. Not written for dry runs. Captures output
+    /// like `junit_report`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html_report: Option<PathBuf>,
+}
+
+fn default_watch_debounce_ms() -> u64 {
+    200
 }
 
 /// A command to execute in a specific directory
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct DirCommand {
     pub dir: String,
     pub cmd: String,
     /// Environment variables to set for this command's subprocess
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub env: Option<HashMap<String, String>>,
+    /// Input written to this command's stdin, overriding `LoopConfig.stdin`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<StdinSource>,
+    /// Shared resources this command uses (e.g. the SSH host it talks to),
+    /// limited in parallel mode by `LoopConfig.resource_limits`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resources: Vec<String>,
+    /// Higher priorities start first under `ScheduleStrategy::Priority`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// Ordered pipeline steps run instead of `cmd`, which then only
+    /// describes the pipeline (see [`Pipeline`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<PipelineStep>,
+    /// Label of the matrix variant this command runs (see
+    /// [`matrix::expand_matrix`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+/// Ordered, named steps to run in one directory. Pipelines run in parallel
+/// across directories while the steps of one pipeline run in order.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Pipeline {
+    pub dir: String,
+    pub steps: Vec<PipelineStep>,
+    /// Environment variables to set for every step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<HashMap<String, String>>,
+}
+
+/// One named command of a [`Pipeline`].
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PipelineStep {
+    pub name: String,
+    pub cmd: String,
+}
+
+impl From<&Pipeline> for DirCommand {
+    fn from(pipeline: &Pipeline) -> Self {
+        DirCommand {
+            dir: pipeline.dir.clone(),
+            cmd: pipeline
+                .steps
+                .iter()
+                .map(|step| step.cmd.as_str())
+                .collect::<Vec<_>>()
+                .join(" && "),
+            env: pipeline.env.clone(),
+            steps: pipeline.steps.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn is_zero(value: &i32) -> bool {
+    *value == 0
+}
+
+/// Strategy for ordering parallel commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleStrategy {
+    /// Start commands in the order given.
+    #[default]
+    ListOrder,
+    /// Start by descending `DirCommand.priority`.
+    Priority,
+    /// Start the directories that took longest in recorded history first,
+    /// so slow repos don't finish last. Needs a history directory.
+    LongestFirst,
+    /// Start in random order (see `shuffle_seed`).
+    Shuffle,
+}
+
+/// Content fed to a command's stdin.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StdinSource {
+    Text(String),
+    Bytes(Vec<u8>),
+    /// Read once before any command starts.
+    File(PathBuf),
+}
+
+impl StdinSource {
+    pub fn load(&self) -> Result<Vec<u8>> {
+        match self {
+            StdinSource::Text(text) => Ok(text.clone().into_bytes()),
+            StdinSource::Bytes(bytes) => Ok(bytes.clone()),
+            StdinSource::File(path) => fs::read(path)
+                .with_context(|| format!("Failed to read stdin file: {}", path.display())),
+        }
+    }
 }
 
 impl Default for LoopConfig {
@@ -104,6 +404,42 @@ impl Default for LoopConfig {
             env: None,
             max_parallel: None,
             root_dir: None,
+            history_dir: None,
+            rerun_failed: false,
+            load_env_files: false,
+            clean_env: false,
+            env_allowlist: vec![],
+            redact_env: vec![],
+            redact_patterns: vec![],
+            capture_limit: None,
+            pty: false,
+            stdin: None,
+            interactive: false,
+            deny_commands: vec![],
+            confirm_commands: vec![],
+            confirm_dangerous: false,
+            allow_dangerous: false,
+            resource_limits: HashMap::new(),
+            adaptive: None,
+            jobserver: false,
+            jobserver_slots: None,
+            schedule: ScheduleStrategy::default(),
+            shuffle_seed: None,
+            continue_on_step_failure: false,
+            watch: false,
+            watch_ignore: Vec::new(),
+            watch_debounce_ms: default_watch_debounce_ms(),
+            cache: false,
+            cache_env: vec![],
+            group_output: false,
+            group_normalize: vec![],
+            diff_baseline: None,
+            table_output: false,
+            table_extract: None,
+            junit_report: None,
+            tap_report: None,
+            markdown_report: None,
+            html_report: None,
         }
     }
 }
@@ -114,8 +450,41 @@ pub struct CommandResult {
     pub exit_code: i32,
     pub directory: PathBuf,
     pub command: String,
+    /// Captured stdout for display; invalid UTF-8 is replaced.
     pub stdout: String,
+    /// Captured stderr for display; invalid UTF-8 is replaced.
     pub stderr: String,
+    /// Captured stdout exactly as produced (after redaction).
+    pub stdout_bytes: Vec<u8>,
+    /// Captured stderr exactly as produced (after redaction).
+    pub stderr_bytes: Vec<u8>,
+    /// Wall-clock time spent running the command (zero for dry runs).
+    pub duration: Duration,
+    /// The command was not run because the user skipped it in interactive
+    /// mode. Skipped commands count as successful.
+    pub skipped: bool,
+    /// Temp file with the complete stdout when it exceeded `capture_limit`.
+    pub stdout_spill: Option<PathBuf>,
+    /// Temp file with the complete stderr when it exceeded `capture_limit`.
+    pub stderr_spill: Option<PathBuf>,
+    /// Per-step outcomes when a pipeline was run. Output of all steps is
+    /// concatenated in `stdout` and `stderr`.
+    pub steps: Vec<StepResult>,
+    /// Matrix variant label of the command.
+    pub variant: Option<String>,
+    /// The command was not run; this is its previous result from the cache.
+    pub cached: bool,
+}
+
+/// Outcome of one pipeline step.
+#[derive(Debug, Clone, Default)]
+pub struct StepResult {
+    pub name: String,
+    pub success: bool,
+    pub exit_code: i32,
+    pub duration: Duration,
+    /// The step was not run because an earlier step failed.
+    pub skipped: bool,
 }
 
 pub fn load_aliases_from_file(path: &Path) -> Result<HashMap<String, String>> {
@@ -130,12 +499,154 @@ pub fn load_aliases_from_file(path: &Path) -> Result<HashMap<String, String>> {
         .collect())
 }
 
-fn prompt_user(question: &str) -> Result<bool> {
-    print!("{question} [y/N]: ");
+/// Prints `prompt` and returns the trimmed, lowercased line typed by the user.
+fn prompt_line(prompt: &str) -> Result<String> {
+    print!("{prompt}: ");
     io::stdout().flush()?;
     let mut input = String::new();
     io::stdin().read_line(&mut input)?;
-    Ok(input.trim().to_lowercase() == "y")
+    Ok(input.trim().to_lowercase())
+}
+
+fn prompt_user(question: &str) -> Result<bool> {
+    Ok(prompt_line(&format!("{question} [y/N]"))? == "y")
+}
+
+/// Answer to the per-directory prompt in interactive mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InteractiveChoice {
+    Run,
+    Skip,
+    /// Run this and all remaining commands without asking.
+    All,
+    /// Skip this and all remaining commands.
+    Quit,
+}
+
+impl InteractiveChoice {
+    fn parse(answer: &str) -> Option<Self> {
+        match answer {
+            "r" | "run" | "y" | "yes" => Some(InteractiveChoice::Run),
+            "s" | "skip" | "n" | "no" | "" => Some(InteractiveChoice::Skip),
+            "a" | "all" => Some(InteractiveChoice::All),
+            "q" | "quit" => Some(InteractiveChoice::Quit),
+            _ => None,
+        }
+    }
+}
+
+fn prompt_interactive_choice(dir: &Path, command: &str) -> Result<InteractiveChoice> {
+    println!("\n{} {}", "→".cyan(), dir.display().to_string().yellow());
+    println!("  $ {command}");
+    loop {
+        let answer = prompt_line("Run here? [r]un / [s]kip / [a]ll / [q]uit (default: skip)")?;
+        if let Some(choice) = InteractiveChoice::parse(&answer) {
+            return Ok(choice);
+        }
+        println!("Please answer r, s, a or q.");
+    }
+}
+
+/// Replaces a leading alias in `command` with its definition.
+fn resolve_alias(command: &str, aliases: &HashMap<String, String>) -> String {
+    command
+        .split_whitespace()
+        .next()
+        .and_then(|cmd| aliases.get(cmd).map(|alias_cmd| (cmd, alias_cmd)))
+        .map(|(cmd, alias_cmd)| command.replacen(cmd, alias_cmd, 1))
+        .unwrap_or_else(|| command.to_string())
+}
+
+/// Returns the alias-resolved commands `dir_cmd` runs: each pipeline step in
+/// order, or its `cmd` when it has no steps.
+fn resolved_commands(dir_cmd: &DirCommand, aliases: &HashMap<String, String>) -> Vec<String> {
+    if dir_cmd.steps.is_empty() {
+        return vec![resolve_alias(&dir_cmd.cmd, aliases)];
+    }
+    dir_cmd
+        .steps
+        .iter()
+        .map(|step| resolve_alias(&step.cmd, aliases))
+        .collect()
+}
+
+/// Returns why the executors must not run `resolved_command`, if the guard
+/// rails forbid it. Confirmation happens up front in the engine, which then
+/// runs with `allow_dangerous`; direct callers get a refusal.
+fn guard_refusal(resolved_command: &str, config: &LoopConfig) -> Option<String> {
+    let guard = match CommandGuard::from_config(config) {
+        Ok(guard) => guard,
+        Err(e) => return Some(format!("{e:#}")),
+    };
+    match guard.check(resolved_command) {
+        GuardDecision::Allow => None,
+        GuardDecision::Deny(pattern) => Some(format!(
+            "Refused to run denied command (matches `{pattern}`); set allow_dangerous to override"
+        )),
+        GuardDecision::Confirm(pattern) => Some(format!(
+            "Refused to run dangerous command without confirmation (matches `{pattern}`); \
+             set allow_dangerous to override"
+        )),
+    }
+}
+
+/// Checks every command against the guard rails before anything runs.
+/// A denied command aborts the run. Each distinct command needing
+/// confirmation is shown once with its directories and must be accepted on a
+/// terminal; without a terminal the run is refused. Returns whether any
+/// command was confirmed.
+fn confirm_dangerous_commands(
+    config: &LoopConfig,
+    commands: &[DirCommand],
+    aliases: &HashMap<String, String>,
+) -> Result<bool> {
+    let guard = CommandGuard::from_config(config)?;
+    let redactor = Redactor::from_config(config, None)?;
+
+    // Group directories by resolved command, keeping first-seen order
+    let mut dangerous: Vec<(String, String, Vec<&str>)> = Vec::new();
+    for dir_cmd in commands {
+        for resolved in resolved_commands(dir_cmd, aliases) {
+            match guard.check(&resolved) {
+                GuardDecision::Allow => {}
+                GuardDecision::Deny(pattern) => anyhow::bail!(
+                    "Refusing to run '{}' (matches deny pattern `{pattern}`); \
+                     set allow_dangerous to override",
+                    redactor.redact(&resolved)
+                ),
+                GuardDecision::Confirm(pattern) => {
+                    match dangerous.iter_mut().find(|(cmd, _, _)| *cmd == resolved) {
+                        Some((_, _, dirs)) => dirs.push(&dir_cmd.dir),
+                        None => dangerous.push((resolved, pattern, vec![&dir_cmd.dir])),
+                    }
+                }
+            }
+        }
+    }
+    if dangerous.is_empty() {
+        return Ok(false);
+    }
+
+    if !io::stdin().is_terminal() {
+        let (command, pattern, _) = &dangerous[0];
+        anyhow::bail!(
+            "Refusing to run '{}' without confirmation (matches `{pattern}`); \
+             set allow_dangerous to override",
+            redactor.redact(command)
+        );
+    }
+    for (command, pattern, dirs) in &dangerous {
+        println!(
+            "\n{} Dangerous command (matches `{pattern}`):",
+            "warning:".yellow()
+        );
+        println!("  $ {}", redactor.redact(command));
+        println!("  in {} directories: {}", dirs.len(), dirs.join(", "));
+        if !prompt_user("Run it anyway?")? {
+            anyhow::bail!("Aborted: '{}' was not confirmed", redactor.redact(command));
+        }
+    }
+    Ok(true)
 }
 
 pub fn add_aliases_to_global_looprc() -> Result<()> {
@@ -223,6 +734,95 @@ pub fn add_aliases_to_global_looprc() -> Result<()> {
     Ok(())
 }
 
+/// Parent environment variables passed through to subprocesses when
+/// `clean_env` is set (in addition to `LoopConfig.env_allowlist`).
+pub const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TERM",
+    "COLORTERM",
+    "LANG",
+    "LC_ALL",
+    "LC_CTYPE",
+    "TZ",
+    "TMPDIR",
+    // Windows essentials
+    "SYSTEMROOT",
+    "WINDIR",
+    "COMSPEC",
+    "PATHEXT",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "TEMP",
+    "TMP",
+];
+
+fn is_env_allowed(name: &str, config: &LoopConfig) -> bool {
+    DEFAULT_ENV_ALLOWLIST
+        .iter()
+        .copied()
+        .chain(config.env_allowlist.iter().map(String::as_str))
+        .any(|allowed| {
+            // Environment variable names are case-insensitive on Windows
+            if cfg!(windows) {
+                allowed.eq_ignore_ascii_case(name)
+            } else {
+                allowed == name
+            }
+        })
+}
+
+/// The parent environment as seen by subprocesses: everything, or only the
+/// allowlisted variables when `clean_env` is set.
+fn inherited_env(config: &LoopConfig) -> HashMap<String, String> {
+    env::vars()
+        .filter(|(name, _)| !config.clean_env || is_env_allowed(name, config))
+        .collect()
+}
+
+/// The complete environment a command in `dir` runs with, layered as
+/// inherited environment, `.env` files (if enabled), then `extra_env`.
+pub(crate) fn child_env(
+    dir: &Path,
+    config: &LoopConfig,
+    extra_env: Option<&HashMap<String, String>>,
+) -> Result<HashMap<String, String>> {
+    let mut env = inherited_env(config);
+    if config.load_env_files {
+        let dotenv = dotenv::env_for_directory(dir, config, &env)?;
+        env.extend(dotenv);
+    }
+
+    // Apply plugin-specified environment variables (e.g., GIT_PAGER=cat)
+    if let Some(extra) = extra_env {
+        env.extend(extra.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+    Ok(env)
+}
+
+/// Builds the shell invocation for a command, running with exactly
+/// `child_env`.
+fn build_shell_command(
+    dir: &Path,
+    resolved_command: &str,
+    child_env: &HashMap<String, String>,
+) -> Command {
+    let (shell, shell_flag) = get_shell_and_flag();
+
+    let mut cmd_builder = Command::new(&shell);
+    cmd_builder
+        .arg(shell_flag)
+        .arg(resolved_command)
+        .current_dir(dir)
+        .env_clear()
+        .envs(child_env);
+    cmd_builder
+}
+
 pub fn execute_command_in_directory(
     dir: &Path,
     command: &str,
@@ -230,30 +830,58 @@ pub fn execute_command_in_directory(
     aliases: &HashMap<String, String>,
     extra_env: Option<&HashMap<String, String>>,
 ) -> CommandResult {
+    execute_command_in_directory_with_stdin(dir, command, config, aliases, extra_env, None)
+}
+
+/// Like [`execute_command_in_directory`], writing `stdin` to the command's
+/// standard input instead of inheriting it.
+pub fn execute_command_in_directory_with_stdin(
+    dir: &Path,
+    command: &str,
+    config: &LoopConfig,
+    aliases: &HashMap<String, String>,
+    extra_env: Option<&HashMap<String, String>>,
+    stdin: Option<&[u8]>,
+) -> CommandResult {
+    // Secrets are looked up in the env the command actually receives
+    let env = child_env(dir, config, extra_env);
+    let redactor = match Redactor::from_config(config, env.as_ref().ok().or(extra_env)) {
+        Ok(redactor) => redactor,
+        Err(e) => {
+            return CommandResult {
+                success: false,
+                exit_code: 1,
+                directory: dir.to_path_buf(),
+                command: command.to_string(),
+                stderr: format!("{e:#}"),
+                ..Default::default()
+            };
+        }
+    };
+
     if !dir.exists() {
         println!("\nNo directory found for {}", dir.display());
         let dir_name = dir.file_name().unwrap_or_default().to_str().unwrap();
         println!(
             "\x1b[31m\n✗ {}: No directory found. Command: {} (Exit code: {})\x1b[0m",
-            dir_name, command, 1
+            dir_name,
+            redactor.redact(command),
+            1
         );
         return CommandResult {
             success: false,
             exit_code: 1,
             directory: dir.to_path_buf(),
-            command: command.to_string(),
+            command: redactor.redact(command).into_owned(),
             stdout: String::new(),
             stderr: String::new(),
+            ..Default::default()
         };
     }
 
     // Resolve aliases for display
-    let resolved_command = command
-        .split_whitespace()
-        .next()
-        .and_then(|cmd| aliases.get(cmd).map(|alias_cmd| (cmd, alias_cmd)))
-        .map(|(cmd, alias_cmd)| command.replacen(cmd, alias_cmd, 1))
-        .unwrap_or_else(|| command.to_string());
+    let resolved_command = resolve_alias(command, aliases);
+    let display_command = redactor.redact(&resolved_command).into_owned();
 
     // Dry run mode: print what would be executed without running it
     if config.dry_run {
@@ -270,15 +898,30 @@ pub fn execute_command_in_directory(
             "{} Would execute in {}:\n  {}",
             "[DRY RUN]".cyan(),
             dir_display.yellow(),
-            resolved_command
+            display_command
         );
         return CommandResult {
             success: true,
             exit_code: 0,
             directory: dir.to_path_buf(),
-            command: resolved_command,
+            command: display_command,
             stdout: String::new(),
             stderr: String::new(),
+            ..Default::default()
+        };
+    }
+
+    if let Some(refusal) = guard_refusal(&resolved_command, config) {
+        if !config.silent {
+            println!("\x1b[31m\n✗ {}: {refusal}\x1b[0m", dir.display());
+        }
+        return CommandResult {
+            success: false,
+            exit_code: 1,
+            directory: dir.to_path_buf(),
+            command: display_command,
+            stderr: refusal,
+            ..Default::default()
         };
     }
 
@@ -291,20 +934,28 @@ pub fn execute_command_in_directory(
         io::stdout().flush().unwrap();
     }
 
-    let (shell, shell_flag) = get_shell_and_flag();
-
-    let mut cmd_builder = Command::new(&shell);
-    cmd_builder
-        .arg(shell_flag)
-        .arg(&resolved_command)
-        .current_dir(dir)
-        .envs(env::vars());
+    let mut cmd_builder = match &env {
+        Ok(env) => build_shell_command(dir, &resolved_command, env),
+        Err(e) => {
+            if !config.silent {
+                println!("\x1b[31m\n✗ {}: {e:#}\x1b[0m", dir.display());
+            }
+            return CommandResult {
+                success: false,
+                exit_code: 1,
+                directory: dir.to_path_buf(),
+                command: display_command,
+                stderr: format!("{e:#}"),
+                ..Default::default()
+            };
+        }
+    };
 
-    // Apply plugin-specified environment variables (e.g., GIT_PAGER=cat)
-    if let Some(extra) = extra_env {
-        cmd_builder.envs(extra);
+    if stdin.is_some() {
+        cmd_builder.stdin(Stdio::piped());
     }
 
+    let started = Instant::now();
     let mut child = cmd_builder
         .stdout(if config.silent {
             Stdio::null()
@@ -320,68 +971,89 @@ pub fn execute_command_in_directory(
         .with_context(|| {
             format!(
                 "Failed to execute command '{}' in directory '{}'",
-                resolved_command,
+                display_command,
                 dir.display()
             )
         })
         .expect("Failed to execute command");
 
+    let writer = match (child.stdin.take(), stdin) {
+        (Some(child_stdin), Some(input)) => {
+            let input = input.to_vec();
+            Some(std::thread::spawn(move || {
+                capture::write_stdin(child_stdin, &input)
+            }))
+        }
+        _ => None,
+    };
     let status = child.wait().expect("Failed to wait on child process");
+    if let Some(writer) = writer {
+        writer.join().ok();
+    }
+    let duration = started.elapsed();
     let exit_code = status.code().unwrap_or(-1);
     let success = status.success();
 
     if !config.silent {
-        // Check if this directory is the root_dir (should display as ".")
-        let is_root = config
-            .root_dir
-            .as_ref()
-            .is_some_and(|root| dir == root.as_path());
-        let dir_name = if is_root {
-            "."
-        } else {
-            dir.file_name()
-                .and_then(|name| name.to_str())
-                .filter(|&s| !s.is_empty())
-                .unwrap_or(".")
-        };
-        if success {
-            if is_root {
-                // Display root as ". (basename)"
-                if let Some(base) = dir.file_name().and_then(|s| s.to_str()) {
-                    println!("\x1b[32m\n✓ . ({base})\x1b[0m");
-                } else {
-                    println!("\x1b[32m\n✓ .\x1b[0m");
-                }
-            } else if dir_name == "." {
-                // Fallback for literal "." paths
-                if let Ok(cwd) = std::env::current_dir() {
-                    if let Some(base) = cwd.file_name().and_then(|s| s.to_str()) {
-                        println!("\x1b[32m\n✓ . ({base})\x1b[0m");
-                    } else {
-                        println!("\x1b[32m\n✓ .\x1b[0m");
-                    }
-                } else {
-                    println!("\x1b[32m\n✓ .\x1b[0m");
-                }
-            } else {
-                println!("\x1b[32m\n✓ {dir_name}\x1b[0m");
-            }
-        } else {
-            println!("\x1b[31m\n✗ {dir_name}: exited code {exit_code}\x1b[0m");
-        }
-        io::stdout().flush().unwrap();
+        print_status_line(dir, success, exit_code, config);
     }
 
     CommandResult {
         success,
         exit_code,
         directory: dir.to_path_buf(),
-        command: resolved_command,
+        command: display_command,
         stdout: String::new(), // Sequential mode uses Stdio::inherit(), so no capture
         stderr: String::new(),
+        duration,
+        ..Default::default()
     }
 }
 
+/// Prints the "✓ name" / "✗ name: exited code N" line that follows a
+/// sequential command's output.
+fn print_status_line(dir: &Path, success: bool, exit_code: i32, config: &LoopConfig) {
+    // Check if this directory is the root_dir (should display as ".")
+    let is_root = config
+        .root_dir
+        .as_ref()
+        .is_some_and(|root| dir == root.as_path());
+    let dir_name = if is_root {
+        "."
+    } else {
+        dir.file_name()
+            .and_then(|name| name.to_str())
+            .filter(|&s| !s.is_empty())
+            .unwrap_or(".")
+    };
+    if success {
+        if is_root {
+            // Display root as ". (basename)"
+            if let Some(base) = dir.file_name().and_then(|s| s.to_str()) {
+                println!("\x1b[32m\n✓ . ({base})\x1b[0m");
+            } else {
+                println!("\x1b[32m\n✓ .\x1b[0m");
+            }
+        } else if dir_name == "." {
+            // Fallback for literal "." paths
+            if let Ok(cwd) = std::env::current_dir() {
+                if let Some(base) = cwd.file_name().and_then(|s| s.to_str()) {
+                    println!("\x1b[32m\n✓ . ({base})\x1b[0m");
+                } else {
+                    println!("\x1b[32m\n✓ .\x1b[0m");
+                }
+            } else {
+                println!("\x1b[32m\n✓ .\x1b[0m");
+            }
+        } else {
+            println!("\x1b[32m\n✓ {dir_name}\x1b[0m");
+        }
+    } else {
+        println!("\x1b[31m\n✗ {dir_name}: exited code {exit_code}\x1b[0m");
+    }
+    io::stdout().flush().unwrap();
+}
+
 /// Capturing version for parallel execution - captures stdout/stderr for display after completion
 pub fn execute_command_in_directory_capturing(
     dir: &Path,
@@ -390,23 +1062,51 @@ pub fn execute_command_in_directory_capturing(
     aliases: &HashMap<String, String>,
     extra_env: Option<&HashMap<String, String>>,
 ) -> CommandResult {
+    execute_command_in_directory_capturing_with_stdin(
+        dir, command, config, aliases, extra_env, None,
+    )
+}
+
+/// Like [`execute_command_in_directory_capturing`], writing `stdin` to the
+/// command's standard input.
+pub fn execute_command_in_directory_capturing_with_stdin(
+    dir: &Path,
+    command: &str,
+    config: &LoopConfig,
+    aliases: &HashMap<String, String>,
+    extra_env: Option<&HashMap<String, String>>,
+    stdin: Option<&[u8]>,
+) -> CommandResult {
+    // Secrets are looked up in the env the command actually receives
+    let env = child_env(dir, config, extra_env);
+    let redactor = match Redactor::from_config(config, env.as_ref().ok().or(extra_env)) {
+        Ok(redactor) => redactor,
+        Err(e) => {
+            return CommandResult {
+                success: false,
+                exit_code: 1,
+                directory: dir.to_path_buf(),
+                command: command.to_string(),
+                stderr: format!("{e:#}"),
+                ..Default::default()
+            };
+        }
+    };
+
     if !dir.exists() {
         return CommandResult {
             success: false,
             exit_code: 1,
             directory: dir.to_path_buf(),
-            command: command.to_string(),
+            command: redactor.redact(command).into_owned(),
             stdout: String::new(),
             stderr: format!("Directory does not exist: {}", dir.display()),
+            ..Default::default()
         };
     }
 
-    let resolved_command = command
-        .split_whitespace()
-        .next()
-        .and_then(|cmd| aliases.get(cmd).map(|alias_cmd| (cmd, alias_cmd)))
-        .map(|(cmd, alias_cmd)| command.replacen(cmd, alias_cmd, 1))
-        .unwrap_or_else(|| command.to_string());
+    let resolved_command = resolve_alias(command, aliases);
+    let display_command = redactor.redact(&resolved_command).into_owned();
 
     // Dry run mode: return what would be executed without running it
     if config.dry_run {
@@ -419,36 +1119,55 @@ pub fn execute_command_in_directory_capturing(
         } else {
             dir.display().to_string()
         };
-        let stdout_msg = format!("[DRY RUN] Would execute in {dir_display}:\n  {resolved_command}");
+        let stdout_msg = format!("[DRY RUN] Would execute in {dir_display}:\n  {display_command}");
         return CommandResult {
             success: true,
             exit_code: 0,
             directory: dir.to_path_buf(),
-            command: resolved_command,
+            command: display_command,
             stdout: stdout_msg,
             stderr: String::new(),
+            ..Default::default()
         };
     }
 
-    let (shell, shell_flag) = get_shell_and_flag();
+    if let Some(refusal) = guard_refusal(&resolved_command, config) {
+        return CommandResult {
+            success: false,
+            exit_code: 1,
+            directory: dir.to_path_buf(),
+            command: display_command,
+            stderr: refusal,
+            ..Default::default()
+        };
+    }
 
-    let mut cmd_builder = Command::new(&shell);
-    cmd_builder
-        .arg(shell_flag)
-        .arg(&resolved_command)
-        .current_dir(dir)
-        .envs(env::vars());
+    let mut cmd_builder = match &env {
+        Ok(env) => build_shell_command(dir, &resolved_command, env),
+        Err(e) => {
+            return CommandResult {
+                success: false,
+                exit_code: 1,
+                directory: dir.to_path_buf(),
+                command: display_command,
+                stderr: format!("{e:#}"),
+                ..Default::default()
+            };
+        }
+    };
 
-    // Apply plugin-specified environment variables (e.g., GIT_PAGER=cat)
-    if let Some(extra) = extra_env {
-        cmd_builder.envs(extra);
-    }
+    let use_pty = config.pty && cfg!(target_os = "linux");
 
-    // Force color output when parent stdout is a TTY
-    // This is needed because piped stdout makes child processes think they're not in a terminal
-    // Note: Tool-specific color vars (e.g., git's GIT_CONFIG_*) should be set by the
-    // plugin that knows about that tool, not here. loop_lib is tool-agnostic.
-    if io::stdout().is_terminal() {
+    if use_pty {
+        // The child sees a real terminal and only needs TERM to describe it
+        if env::var("TERM").is_err() {
+            cmd_builder.env("TERM", "xterm-256color");
+        }
+    } else if io::stdout().is_terminal() {
+        // Force color output when parent stdout is a TTY
+        // This is needed because piped stdout makes child processes think they're not in a terminal
+        // Note: Tool-specific color vars (e.g., git's GIT_CONFIG_*) should be set by the
+        // plugin that knows about that tool, not here. loop_lib is tool-agnostic.
         cmd_builder
             .env("FORCE_COLOR", "1") // Node.js ecosystem
             .env("CLICOLOR_FORCE", "1"); // BSD/macOS convention
@@ -458,35 +1177,185 @@ pub fn execute_command_in_directory_capturing(
         }
     }
 
-    let output = cmd_builder
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output();
+    let label = dir.file_name().and_then(|n| n.to_str()).unwrap_or("root");
+    let started = Instant::now();
+    #[cfg(target_os = "linux")]
+    let output = if use_pty {
+        pty::run_in_pty(
+            &mut cmd_builder,
+            stdin,
+            config.capture_limit,
+            label,
+            &redactor,
+        )
+        .map(|(status, stdout)| (status, stdout, Default::default()))
+    } else {
+        capture::run_piped(
+            &mut cmd_builder,
+            stdin,
+            config.capture_limit,
+            label,
+            &redactor,
+        )
+    };
+    #[cfg(not(target_os = "linux"))]
+    let output = capture::run_piped(
+        &mut cmd_builder,
+        stdin,
+        config.capture_limit,
+        label,
+        &redactor,
+    );
+    let duration = started.elapsed();
 
     match output {
-        Ok(output) => {
-            let success = output.status.success();
-            let exit_code = output.status.code().unwrap_or(-1);
+        Ok((status, stdout, stderr)) => {
+            let stdout_bytes = redactor.redact_bytes(&stdout.bytes).into_owned();
+            let stderr_bytes = redactor.redact_bytes(&stderr.bytes).into_owned();
             CommandResult {
-                success,
-                exit_code,
+                success: status.success(),
+                exit_code: status.code().unwrap_or(-1),
                 directory: dir.to_path_buf(),
-                command: resolved_command,
-                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                command: display_command,
+                stdout: String::from_utf8_lossy(&stdout_bytes).into_owned(),
+                stderr: String::from_utf8_lossy(&stderr_bytes).into_owned(),
+                stdout_bytes,
+                stderr_bytes,
+                stdout_spill: stdout.spill,
+                stderr_spill: stderr.spill,
+                duration,
+                ..Default::default()
             }
         }
         Err(e) => CommandResult {
             success: false,
             exit_code: -1,
             directory: dir.to_path_buf(),
-            command: resolved_command,
+            command: display_command,
             stdout: String::new(),
-            stderr: format!("Failed to execute: {e}"),
+            stderr: redactor
+                .redact(&format!("Failed to execute: {e}"))
+                .into_owned(),
+            duration,
+            ..Default::default()
         },
     }
 }
 
+/// Prints one line per pipeline step with its outcome and timing.
+fn print_step_results(steps: &[StepResult]) {
+    for step in steps {
+        if step.skipped {
+            println!("  {} {} (not run)", "-".yellow(), step.name);
+        } else if step.success {
+            println!(
+                "  {} {} ({:.1}s)",
+                "✓".green(),
+                step.name,
+                step.duration.as_secs_f64()
+            );
+        } else {
+            println!(
+                "  {} {} ({:.1}s, exit code {})",
+                "✗".red(),
+                step.name,
+                step.duration.as_secs_f64(),
+                step.exit_code
+            );
+        }
+    }
+}
+
+/// Runs a command, or each step of a pipeline in order, in its directory.
+/// After a failed step the remaining steps are skipped unless
+/// `continue_on_step_failure` is set.
+fn execute_dir_command(
+    dir_cmd: &DirCommand,
+    config: &LoopConfig,
+    aliases: &HashMap<String, String>,
+    extra_env: Option<&HashMap<String, String>>,
+    stdin: Option<&[u8]>,
+    capturing: bool,
+) -> CommandResult {
+    let dir = PathBuf::from(&dir_cmd.dir);
+    if let Some(variant) = &dir_cmd.variant {
+        if !capturing && !config.silent && !config.dry_run {
+            println!("\n{} {} [{variant}]", "▸".cyan(), dir.display());
+        }
+    }
+    let mut result = execute_steps(dir_cmd, &dir, config, aliases, extra_env, stdin, capturing);
+    result.variant = dir_cmd.variant.clone();
+    result
+}
+
+/// Runs the command or pipeline steps of `dir_cmd`.
+fn execute_steps(
+    dir_cmd: &DirCommand,
+    dir: &Path,
+    config: &LoopConfig,
+    aliases: &HashMap<String, String>,
+    extra_env: Option<&HashMap<String, String>>,
+    stdin: Option<&[u8]>,
+    capturing: bool,
+) -> CommandResult {
+    let execute = |command: &str| {
+        if capturing {
+            execute_command_in_directory_capturing_with_stdin(
+                dir, command, config, aliases, extra_env, stdin,
+            )
+        } else {
+            execute_command_in_directory_with_stdin(dir, command, config, aliases, extra_env, stdin)
+        }
+    };
+    if dir_cmd.steps.is_empty() {
+        return execute(&dir_cmd.cmd);
+    }
+
+    let mut combined = CommandResult {
+        directory: dir.to_path_buf(),
+        ..Default::default()
+    };
+    let mut commands = Vec::new();
+    let mut failed = false;
+    for step in &dir_cmd.steps {
+        if failed && !config.continue_on_step_failure {
+            combined.steps.push(StepResult {
+                name: step.name.clone(),
+                skipped: true,
+                ..Default::default()
+            });
+            continue;
+        }
+        if !capturing && !config.silent && !config.dry_run {
+            println!("\n{} {}: {}", "▸".cyan(), dir.display(), step.name.bold());
+        }
+
+        let result = execute(&step.cmd);
+        if !result.success && !failed {
+            combined.exit_code = result.exit_code;
+        }
+        failed |= !result.success;
+        commands.push(result.command);
+        combined.stdout.push_str(&result.stdout);
+        combined.stderr.push_str(&result.stderr);
+        combined.stdout_bytes.extend(result.stdout_bytes);
+        combined.stderr_bytes.extend(result.stderr_bytes);
+        combined.duration += result.duration;
+        combined.stdout_spill = combined.stdout_spill.or(result.stdout_spill);
+        combined.stderr_spill = combined.stderr_spill.or(result.stderr_spill);
+        combined.steps.push(StepResult {
+            name: step.name.clone(),
+            success: result.success,
+            exit_code: result.exit_code,
+            duration: result.duration,
+            skipped: false,
+        });
+    }
+    combined.success = !failed;
+    combined.command = commands.join(" && ");
+    combined
+}
+
 pub fn expand_directories(directories: &[String], ignore: &[String]) -> Result<Vec<String>> {
     let mut expanded = Vec::new();
 
@@ -500,6 +1369,10 @@ pub fn expand_directories(directories: &[String], ignore: &[String]) -> Result<V
             for entry in fs::read_dir(&dir_path)? {
                 let entry = entry?;
                 let path = entry.path();
+                // Skip loop's own state directory (run history etc.)
+                if entry.file_name() == history::STATE_DIR_NAME {
+                    continue;
+                }
                 if path.is_dir() && !should_ignore(&path, ignore) {
                     expanded.push(path.to_string_lossy().into_owned());
                 }
@@ -518,72 +1391,139 @@ pub fn run(orig_config: &LoopConfig, command: &str) -> Result<()> {
         return add_aliases_to_global_looprc();
     }
 
-    // Apply include/exclude filters to directories
-    let mut dirs = orig_config.directories.clone();
-
-    if let Some(ref includes) = orig_config.include_filters {
-        if !includes.is_empty() {
-            dirs.retain(|p| includes.iter().any(|f| p.contains(f)));
-        }
-    }
-
-    if let Some(ref excludes) = orig_config.exclude_filters {
-        if !excludes.is_empty() {
-            if orig_config.verbose {
-                println!("Exclude filters: {excludes:?}");
-            }
-            dirs.retain(|p| {
-                let excluded = excludes.iter().any(|f| {
-                    let f = f.trim_end_matches('/');
-                    p.contains(f)
-                });
-                if orig_config.verbose {
-                    println!("Dir: {p}, excluded: {excluded}");
-                }
-                !excluded
-            });
-        }
-    }
-
     // Build DirCommand list with same command for each directory
-    let commands: Vec<DirCommand> = dirs
+    let commands: Vec<DirCommand> = orig_config
+        .directories
         .iter()
         .map(|dir| DirCommand {
             dir: dir.clone(),
             cmd: command.to_string(),
             env: orig_config.env.clone(),
+            ..Default::default()
         })
         .collect();
 
     // Delegate to unified execution engine
-    execute_commands_internal(orig_config, &commands)
+    let config = &*stdout_report_config(orig_config)?;
+    execute_commands_internal(config, &prepare_commands(config, &commands)?)
 }
 
 /// JSON output structure for command results
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct JsonOutput {
     pub success: bool,
     pub results: Vec<JsonCommandResult>,
     pub summary: JsonSummary,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct JsonCommandResult {
     pub directory: String,
     pub command: String,
     pub success: bool,
     pub exit_code: i32,
-    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub stdout: String,
-    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub stderr: String,
+    /// Set when `stdout` is not valid UTF-8 and has been encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_encoding: Option<OutputEncoding>,
+    /// Set when `stderr` is not valid UTF-8 and has been encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_encoding: Option<OutputEncoding>,
+    /// Complete stdout when it exceeded the capture limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_file: Option<PathBuf>,
+    /// Complete stderr when it exceeded the capture limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<JsonStepResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JsonStepResult {
+    pub name: String,
+    pub success: bool,
+    pub exit_code: i32,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+}
+
+impl From<&StepResult> for JsonStepResult {
+    fn from(step: &StepResult) -> Self {
+        JsonStepResult {
+            name: step.name.clone(),
+            success: step.success,
+            exit_code: step.exit_code,
+            duration_ms: step.duration.as_millis() as u64,
+            skipped: step.skipped,
+        }
+    }
+}
+
+/// Encoding of output that is not valid UTF-8 in JSON results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEncoding {
+    Base64,
+}
+
+/// Returns the JSON representation of captured output: the display string
+/// when the raw bytes are valid UTF-8, otherwise the base64-encoded bytes.
+fn json_output_field(text: &str, bytes: &[u8]) -> (String, Option<OutputEncoding>) {
+    if std::str::from_utf8(bytes).is_ok() {
+        (text.to_string(), None)
+    } else {
+        (BASE64_STANDARD.encode(bytes), Some(OutputEncoding::Base64))
+    }
+}
+
+impl From<&CommandResult> for JsonCommandResult {
+    fn from(r: &CommandResult) -> Self {
+        let (stdout, stdout_encoding) = json_output_field(&r.stdout, &r.stdout_bytes);
+        let (stderr, stderr_encoding) = json_output_field(&r.stderr, &r.stderr_bytes);
+        JsonCommandResult {
+            directory: r.directory.display().to_string(),
+            command: r.command.clone(),
+            success: r.success,
+            exit_code: r.exit_code,
+            duration_ms: r.duration.as_millis() as u64,
+            skipped: r.skipped,
+            stdout,
+            stderr,
+            stdout_encoding,
+            stderr_encoding,
+            stdout_file: r.stdout_spill.clone(),
+            stderr_file: r.stderr_spill.clone(),
+            steps: r.steps.iter().map(JsonStepResult::from).collect(),
+            variant: r.variant.clone(),
+            cached: r.cached,
+        }
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct JsonSummary {
     pub total: usize,
+    /// Includes skipped commands.
     pub succeeded: usize,
     pub failed: usize,
+    #[serde(default)]
+    pub skipped: usize,
+    /// Successful commands whose result came from the cache.
+    #[serde(default)]
+    pub cached: usize,
     pub dry_run: bool,
 }
 
@@ -594,14 +1534,138 @@ pub struct JsonSummary {
 /// Internal execution engine that handles both parallel and sequential execution.
 /// This is the unified implementation used by both `run()` and `run_commands()`.
 fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Result<()> {
+    if config.watch && !config.dry_run && !commands.is_empty() {
+        return watch::watch_commands(config, commands);
+    }
+    let results = execute_and_report(config, commands)?;
+    check_failures(config, &results)
+}
+
+/// Fails if any command failed outside a dry run.
+fn check_failures(config: &LoopConfig, results: &[CommandResult]) -> Result<()> {
+    if results.iter().any(|r| !r.success) && !config.dry_run {
+        return Err(anyhow::anyhow!("At least one command failed"));
+    }
+    Ok(())
+}
+
+/// Runs the commands, records history and prints their output and summary,
+/// returning the results. Command failures are reported in the results, not
+/// as an error.
+fn execute_and_report(config: &LoopConfig, commands: &[DirCommand]) -> Result<Vec<CommandResult>> {
+    let config = &*stdout_report_config(config)?;
     if commands.is_empty() {
-        return Ok(());
+        return Ok(Vec::new());
+    }
+
+    // Fail fast on invalid redaction patterns rather than once per command
+    Redactor::from_config(config, None)?;
+    let group_output = config.group_output && !config.json_output && !config.dry_run;
+    if group_output {
+        group::group_outputs(&[], &config.group_normalize)?;
+    }
+    let diff_baseline = match &config.diff_baseline {
+        Some(baseline) if !config.json_output && !config.dry_run => {
+            Some(baseline::resolve_baseline(baseline, commands)?)
+        }
+        _ => None,
+    };
+    let table_output = config.table_output && !config.json_output && !config.dry_run;
+    let table_extract = match &config.table_extract {
+        Some(pattern) if table_output => Some(
+            regex::Regex::new(pattern)
+                .with_context(|| format!("Invalid table extract pattern: {pattern}"))?,
+        ),
+        _ => None,
+    };
+    // Modes that replace the per-directory output
+    let summarize_output = group_output || diff_baseline.is_some() || table_output;
+    let reports = [
+        &config.junit_report,
+        &config.tap_report,
+        &config.markdown_report,
+        &config.html_report,
+    ];
+    // Report files include each command's output
+    let capture_for_reports = !config.dry_run && reports.iter().any(|path| path.is_some());
+
+    // Load stdin inputs once up front; every command gets its own copy of the
+    // full input when it starts
+    let shared_stdin = config.stdin.as_ref().map(StdinSource::load).transpose()?;
+    let command_stdin: Vec<Option<Vec<u8>>> = commands
+        .iter()
+        .map(|c| c.stdin.as_ref().map(StdinSource::load).transpose())
+        .collect::<Result<_>>()?;
+    let stdin_for = |i: usize| command_stdin[i].as_deref().or(shared_stdin.as_deref());
+
+    let interactive = config.interactive && !config.dry_run;
+    if interactive && !io::stdin().is_terminal() {
+        anyhow::bail!("Interactive mode requires a terminal on stdin");
     }
 
     let results = Arc::new(Mutex::new(Vec::new()));
     let aliases = Arc::new(get_aliases());
 
-    if config.parallel {
+    // Once dangerous commands are confirmed, run with the guard disabled so
+    // the executors don't refuse them
+    let confirmed_config;
+    let config = if !config.dry_run && confirm_dangerous_commands(config, commands, &aliases)? {
+        confirmed_config = LoopConfig {
+            allow_dangerous: true,
+            ..config.clone()
+        };
+        &confirmed_config
+    } else {
+        config
+    };
+
+    // Share the jobserver with every command through MAKEFLAGS, unless a
+    // command sets its own
+    let jobserver = if config.dry_run {
+        None
+    } else {
+        Jobserver::for_config(config)?
+    };
+    let command_env: Vec<Option<HashMap<String, String>>> = commands
+        .iter()
+        .map(|c| {
+            let jobserver = jobserver.as_ref()?;
+            let mut env = c.env.clone().unwrap_or_default();
+            env.entry("MAKEFLAGS".to_string())
+                .or_insert_with(|| jobserver.makeflags().to_string());
+            Some(env)
+        })
+        .collect();
+    let env_for = |i: usize| command_env[i].as_ref().or(commands[i].env.as_ref());
+
+    // Report unchanged directories from the cache instead of running them
+    let cache = if config.cache && !config.dry_run {
+        Some(RunCache::load(config, commands.len())?)
+    } else {
+        None
+    };
+    let execute = |i: usize, capturing: bool| {
+        let dir_cmd = &commands[i];
+        if let Some(cache) = &cache {
+            if let Some(result) = cache.lookup(i, dir_cmd, config, &aliases, stdin_for(i)) {
+                return result;
+            }
+        }
+        let result = execute_dir_command(
+            dir_cmd,
+            config,
+            &aliases,
+            env_for(i),
+            stdin_for(i),
+            capturing,
+        );
+        if let Some(cache) = &cache {
+            cache.store(i, dir_cmd, &result);
+        }
+        result
+    };
+
+    if config.parallel && !interactive {
         // Parallel execution using rayon thread pool with spinners
         let is_tty = std::io::stdout().is_terminal() && !config.json_output;
         let mp = if is_tty {
@@ -623,7 +1687,12 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
                 if let Some(ref mp) = mp {
                     let pb = mp.add(ProgressBar::new_spinner());
                     pb.set_style(spinner_style.clone());
-                    pb.set_prefix(format!("[{}/{}]", i + 1, total));
+                    let variant = dir_cmd
+                        .variant
+                        .as_ref()
+                        .map(|v| format!(" {v}"))
+                        .unwrap_or_default();
+                    pb.set_prefix(format!("[{}/{}]{variant}", i + 1, total));
                     let dir_path = PathBuf::from(&dir_cmd.dir);
                     let is_root = config.root_dir.as_ref().is_some_and(|r| dir_path == *r);
                     let dir_name = if is_root {
@@ -648,74 +1717,109 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
         let spawn_counter = Arc::new(AtomicUsize::new(0));
         let stagger_ms = config.spawn_stagger_ms;
 
-        // Closure to execute commands in parallel
-        let execute_parallel = || {
-            commands
-                .par_iter()
-                .enumerate()
-                .map(|(i, dir_cmd)| {
-                    // Apply stagger delay to spread out connection attempts.
-                    // Each thread gets a slot number and sleeps proportionally.
-                    if stagger_ms > 0 {
-                        let slot = spawn_counter.fetch_add(1, Ordering::SeqCst);
-                        let delay = Duration::from_millis(stagger_ms * slot as u64);
-                        std::thread::sleep(delay);
-                    }
+        let scheduler = Scheduler::new(commands, &config.resource_limits)?
+            .with_order(schedule_order(config, commands)?);
+
+        // Runs one command, updating its spinner
+        let run_one = |i: usize, dir_cmd: &DirCommand| {
+            // Apply stagger delay to spread out connection attempts.
+            // Each thread gets a slot number and sleeps proportionally.
+            if stagger_ms > 0 {
+                let slot = spawn_counter.fetch_add(1, Ordering::SeqCst);
+                let delay = Duration::from_millis(stagger_ms * slot as u64);
+                std::thread::sleep(delay);
+            }
 
-                    let dir = PathBuf::from(&dir_cmd.dir);
-                    let is_root = config.root_dir.as_ref().is_some_and(|r| dir == *r);
-                    let dir_name = if is_root {
-                        ".".to_string()
-                    } else {
-                        dir.file_name()
-                            .and_then(|n| n.to_str())
-                            .unwrap_or(".")
-                            .to_string()
-                    };
+            let dir = PathBuf::from(&dir_cmd.dir);
+            let is_root = config.root_dir.as_ref().is_some_and(|r| dir == *r);
+            let dir_name = if is_root {
+                ".".to_string()
+            } else {
+                dir.file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or(".")
+                    .to_string()
+            };
 
-                    // Update spinner to show running
-                    if let Some(ref pb) = spinners[i] {
-                        pb.set_message(format!("{dir_name}: running..."));
-                    }
+            // Update spinner to show running
+            if let Some(ref pb) = spinners[i] {
+                pb.set_message(format!("{dir_name}: running..."));
+            }
 
-                    let result = execute_command_in_directory_capturing(
-                        &dir,
-                        &dir_cmd.cmd,
-                        config,
-                        &aliases,
-                        dir_cmd.env.as_ref(),
-                    );
+            let result = execute(i, true);
 
-                    // Update spinner with result (only if not JSON output)
-                    // Note: We don't print completion status here - detailed results shown after all complete
-                    if !config.json_output {
-                        if let Some(ref pb) = spinners[i] {
-                            // Clear spinner - detailed results shown later
-                            pb.finish_and_clear();
-                        }
-                        // Non-TTY: no per-command output, detailed results shown after all complete
-                    }
+            // Update spinner with result (only if not JSON output)
+            // Note: We don't print completion status here - detailed results shown after all complete
+            if !config.json_output {
+                if let Some(ref pb) = spinners[i] {
+                    // Clear spinner - detailed results shown later
+                    pb.finish_and_clear();
+                }
+                // Non-TTY: no per-command output, detailed results shown after all complete
+            }
 
-                    result
-                })
-                .collect()
+            result
         };
 
-        // Use custom thread pool if max_parallel is set, otherwise use global pool
-        let parallel_results: Vec<CommandResult> = if let Some(max) = config.max_parallel {
-            // Create a custom thread pool with limited threads
-            let pool = ThreadPoolBuilder::new()
-                .num_threads(max)
-                .build()
-                .expect("Failed to create thread pool");
-            pool.install(execute_parallel)
-        } else {
-            execute_parallel()
+        // Closure to execute commands in parallel. Each worker takes the next
+        // command whose resources are free, so a command waiting on a busy
+        // resource doesn't hold up the others.
+        let execute_parallel = || {
+            let workers = rayon::current_num_threads().min(total);
+            let finished = Mutex::new(Vec::with_capacity(total));
+            let (stop_monitor, stopped) = mpsc::channel::<()>();
+            // The first limit applies before any worker starts a command
+            let adaptive = config.adaptive.as_ref().and_then(|adaptive| {
+                adaptive::start(&scheduler, workers, adaptive).map(|limit| (adaptive, limit))
+            });
+            std::thread::scope(|monitor_scope| {
+                if let Some((adaptive, limit)) = adaptive {
+                    let scheduler = &scheduler;
+                    monitor_scope.spawn(move || {
+                        adaptive::monitor(scheduler, limit, workers, adaptive, stopped)
+                    });
+                }
+                rayon::scope(|s| {
+                    for _ in 0..workers {
+                        s.spawn(|_| {
+                            while let Some(i) = scheduler.next() {
+                                // Each running command holds a job slot; a
+                                // broken jobserver shouldn't stop the run
+                                let _token = jobserver.as_ref().and_then(|j| j.acquire().ok());
+                                let result = run_one(i, &commands[i]);
+                                scheduler.finish(i);
+                                finished
+                                    .lock()
+                                    .unwrap_or_else(|e| e.into_inner())
+                                    .push((i, result));
+                            }
+                        });
+                    }
+                });
+                drop(stop_monitor);
+            });
+            finished.into_inner().unwrap_or_else(|e| e.into_inner())
         };
 
+        // Use custom thread pool if max_parallel is set, otherwise use global pool
+        let mut parallel_results: Vec<(usize, CommandResult)> =
+            if let Some(max) = config.max_parallel {
+                // Create a custom thread pool with limited threads
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(max)
+                    .build()
+                    .expect("Failed to create thread pool");
+                pool.install(execute_parallel)
+            } else {
+                execute_parallel()
+            };
+
         // Store results (already collected from rayon), sorted for deterministic output
-        // Sort by directory name: root_dir first (displayed as "."), then alphabetical
-        let mut sorted_results = parallel_results;
+        // Sort by directory name: root_dir first (displayed as "."), then alphabetical,
+        // keeping list order within a directory
+        parallel_results.sort_by_key(|(i, _)| *i);
+        let mut sorted_results: Vec<CommandResult> =
+            parallel_results.into_iter().map(|(_, r)| r).collect();
         sorted_results.sort_by(|a, b| {
             let a_is_root = config.root_dir.as_ref().is_some_and(|r| a.directory == *r);
             let b_is_root = config.root_dir.as_ref().is_some_and(|r| b.directory == *r);
@@ -752,11 +1856,11 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
         }
 
         // Print captured output after all spinners complete (if not JSON)
-        if !config.silent && !config.json_output {
+        if !config.silent && !config.json_output && !summarize_output {
             let results = results.lock().unwrap_or_else(|e| e.into_inner());
             let has_any_output = results
                 .iter()
-                .any(|r| !r.stdout.trim().is_empty() || !r.stderr.trim().is_empty());
+                .any(|r| r.cached || !r.stdout.trim().is_empty() || !r.stderr.trim().is_empty());
 
             if has_any_output {
                 println!();
@@ -783,10 +1887,17 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
                         .unwrap_or(".")
                         .to_string()
                 };
+                let dir_name = match &result.variant {
+                    Some(variant) => format!("{dir_name} [{variant}]"),
+                    None => dir_name,
+                };
 
-                let has_output =
-                    !result.stdout.trim().is_empty() || !result.stderr.trim().is_empty();
-                if has_output {
+                let has_output = !result.stdout.trim().is_empty()
+                    || !result.stderr.trim().is_empty()
+                    || !result.steps.is_empty();
+                if result.cached {
+                    println!("{} {} (cached)", "✓".green(), dir_name.green());
+                } else if has_output {
                     if result.success {
                         println!("{} {}:", "✓".green(), dir_name.green());
                     } else {
@@ -798,32 +1909,79 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
                     if !result.stderr.trim().is_empty() {
                         print!("{}", result.stderr);
                     }
+                    print_step_results(&result.steps);
                     println!(); // Blank line after each repo's output
                 }
             }
         }
     } else {
         // Sequential execution
-        for dir_cmd in commands {
+        let mut confirm_each = interactive;
+        let mut quit = false;
+        for (i, dir_cmd) in commands.iter().enumerate() {
             let dir = PathBuf::from(&dir_cmd.dir);
-            let result = if config.json_output {
-                // Capture output for JSON mode
-                execute_command_in_directory_capturing(
-                    &dir,
-                    &dir_cmd.cmd,
-                    config,
-                    &aliases,
-                    dir_cmd.env.as_ref(),
-                )
-            } else {
-                execute_command_in_directory(
-                    &dir,
-                    &dir_cmd.cmd,
-                    config,
-                    &aliases,
-                    dir_cmd.env.as_ref(),
-                )
-            };
+
+            if quit || confirm_each {
+                let env = child_env(&dir, config, dir_cmd.env.as_ref()).ok();
+                let command = Redactor::from_config(config, env.as_ref().or(dir_cmd.env.as_ref()))?
+                    .redact(&resolved_commands(dir_cmd, &aliases).join(" && "))
+                    .into_owned();
+                let choice = if quit {
+                    InteractiveChoice::Quit
+                } else {
+                    prompt_interactive_choice(&dir, &command)?
+                };
+                match choice {
+                    InteractiveChoice::Run => {}
+                    InteractiveChoice::All => confirm_each = false,
+                    InteractiveChoice::Skip | InteractiveChoice::Quit => {
+                        quit = choice == InteractiveChoice::Quit;
+                        if !config.silent && !config.json_output {
+                            println!("{} {}: skipped", "-".yellow(), dir.display());
+                        }
+                        results
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .push(CommandResult {
+                                success: true,
+                                directory: dir,
+                                command,
+                                skipped: true,
+                                variant: dir_cmd.variant.clone(),
+                                ..Default::default()
+                            });
+                        continue;
+                    }
+                }
+            }
+
+            // Capture output for JSON mode, output summaries and reports
+            let result = execute(
+                i,
+                config.json_output || summarize_output || capture_for_reports,
+            );
+            if result.cached && !config.silent && !config.json_output {
+                let variant = dir_cmd
+                    .variant
+                    .as_ref()
+                    .map(|v| format!(" [{v}]"))
+                    .unwrap_or_default();
+                println!("{} {}{variant} (cached)", "✓".green(), dir.display());
+            } else if capture_for_reports
+                && !config.silent
+                && !config.json_output
+                && !summarize_output
+            {
+                // Show the output captured only for the reports as it would
+                // have been streamed
+                if let Some(variant) = &dir_cmd.variant {
+                    println!("\n{} {} [{variant}]", "▸".cyan(), dir.display());
+                }
+                println!();
+                print!("{}{}", result.stdout, result.stderr);
+                print_step_results(&result.steps);
+                print_status_line(&dir, result.success, result.exit_code, config);
+            }
             results
                 .lock()
                 .unwrap_or_else(|e| e.into_inner())
@@ -832,25 +1990,57 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
     }
 
     // Build results summary
-    let results = results.lock().unwrap_or_else(|e| e.into_inner());
+    let results: Vec<CommandResult> =
+        std::mem::take(&mut *results.lock().unwrap_or_else(|e| e.into_inner()));
+
+    if let Some(cache) = &cache {
+        if let Err(e) = cache.save() {
+            if !config.silent {
+                eprintln!("{} Failed to save result cache: {e:#}", "warning:".yellow());
+            }
+        }
+    }
+
+    // Record the run so failures can be retried later
+    if !config.dry_run {
+        if let Some(dir) = history::history_dir(config) {
+            if let Err(e) = history::record_run(&dir, config, &results) {
+                if !config.silent {
+                    eprintln!(
+                        "{} Failed to record run history: {e:#}",
+                        "warning:".yellow()
+                    );
+                }
+            }
+        }
+    }
+    if !config.dry_run {
+        write_reports(config, &results)?;
+    }
+
     let total = results.len();
     let failed: Vec<_> = results.iter().filter(|r| !r.success).collect();
     let failed_count = failed.len();
+    let skipped_count = results.iter().filter(|r| r.skipped).count();
+    let cached_count = results.iter().filter(|r| r.cached).count();
+
+    if group_output && !config.silent {
+        let groups = group::group_outputs(&results, &config.group_normalize)?;
+        print!("\n{}", group::render_groups(&groups));
+    }
+    if let Some(baseline) = diff_baseline.as_ref().filter(|_| !config.silent) {
+        println!("\nDiff against baseline {}:", baseline.display());
+        println!(
+            "{}",
+            baseline::render_baseline_diffs_colored(&results, baseline)
+        );
+    }
 
     // Output results
     if config.json_output {
         // JSON output mode
-        let json_results: Vec<JsonCommandResult> = results
-            .iter()
-            .map(|r| JsonCommandResult {
-                directory: r.directory.display().to_string(),
-                command: r.command.clone(),
-                success: r.success,
-                exit_code: r.exit_code,
-                stdout: r.stdout.clone(),
-                stderr: r.stderr.clone(),
-            })
-            .collect();
+        let json_results: Vec<JsonCommandResult> =
+            results.iter().map(JsonCommandResult::from).collect();
 
         let output = JsonOutput {
             success: failed_count == 0,
@@ -859,11 +2049,26 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
                 total,
                 succeeded: total - failed_count,
                 failed: failed_count,
+                skipped: skipped_count,
+                cached: cached_count,
                 dry_run: config.dry_run,
             },
         };
 
         println!("{}", serde_json::to_string_pretty(&output)?);
+    } else if table_output {
+        if !config.silent {
+            let is_tty = io::stdout().is_terminal();
+            let width = if is_tty {
+                table::terminal_width()
+            } else {
+                None
+            };
+            print!(
+                "\n{}",
+                table::render_table(&results, table_extract.as_ref(), width, is_tty)
+            );
+        }
     } else if !config.silent {
         // Text output mode
         if config.dry_run {
@@ -874,7 +2079,17 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
                 total.to_string().yellow()
             );
         } else if failed_count == 0 {
-            println!("{} commands complete", total.to_string().green());
+            let mut summary = format!(
+                "{} commands complete",
+                (total - skipped_count).to_string().green()
+            );
+            if cached_count > 0 {
+                summary.push_str(&format!(" ({cached_count} cached)"));
+            }
+            if skipped_count > 0 {
+                summary.push_str(&format!(", {} skipped", skipped_count.to_string().yellow()));
+            }
+            println!("{summary}");
         } else {
             println!(
                 "\nSummary: {} {} out of {} commands failed",
@@ -883,50 +2098,159 @@ fn execute_commands_internal(config: &LoopConfig, commands: &[DirCommand]) -> Re
                 total
             );
             for result in &failed {
+                let variant = result
+                    .variant
+                    .as_ref()
+                    .map(|v| format!(" [{v}]"))
+                    .unwrap_or_default();
                 println!(
-                    "\n{} {}: {} (Exit code {}) ",
+                    "\n{} {}{variant}: {} (Exit code {}) ",
                     "✗".red(),
                     result.directory.display(),
                     result.command,
                     result.exit_code
                 );
+                print_step_results(&result.steps);
+            }
+            if skipped_count > 0 {
+                println!("\n{} {} skipped", "-".yellow(), skipped_count);
             }
             println!();
         }
     }
 
-    if failed_count > 0 && !config.dry_run {
-        return Err(anyhow::anyhow!("At least one command failed"));
+    Ok(results)
+}
+
+/// Returns `config` with `silent` set when a report is written to stdout
+/// (`-`), so that the report is the only thing printed there. Fails when
+/// more than one report, or a report and JSON output, target stdout.
+fn stdout_report_config(config: &LoopConfig) -> Result<Cow<'_, LoopConfig>> {
+    let to_stdout = [
+        &config.junit_report,
+        &config.tap_report,
+        &config.markdown_report,
+        &config.html_report,
+    ]
+    .iter()
+    .filter(|path| path.as_deref() == Some(Path::new("-")))
+    .count();
+    if to_stdout > 1 {
+        anyhow::bail!("Only one report can be written to stdout (`-`)");
+    }
+    if to_stdout == 1 && config.json_output {
+        anyhow::bail!("A report can't be written to stdout (`-`) together with JSON output");
     }
+    if to_stdout == 1 && !config.silent {
+        return Ok(Cow::Owned(LoopConfig {
+            silent: true,
+            ..config.clone()
+        }));
+    }
+    Ok(Cow::Borrowed(config))
+}
 
+/// Writes the report files requested in `config`.
+fn write_reports(config: &LoopConfig, results: &[CommandResult]) -> Result<()> {
+    if let Some(path) = &config.junit_report {
+        write_report(path, "JUnit", &report::junit_xml(results))?;
+    }
+    if let Some(path) = &config.tap_report {
+        write_report(path, "TAP", &report::tap(results))?;
+    }
+    if let Some(path) = &config.markdown_report {
+        write_report(path, "Markdown", &report::markdown(results))?;
+    }
+    if let Some(path) = &config.html_report {
+        write_report(path, "HTML", &report::html(results))?;
+    }
     Ok(())
 }
 
+/// Writes a report to `path`, or to stdout for `-`.
+fn write_report(path: &Path, kind: &str, content: &str) -> Result<()> {
+    if path == Path::new("-") {
+        print!("{content}");
+        return Ok(());
+    }
+    fs::write(path, content)
+        .with_context(|| format!("Failed to write {kind} report: {}", path.display()))
+}
+
 /// Execute a list of commands (each with its own directory)
 /// This is the unified execution engine for plugins.
 /// Applies include/exclude filters from config before executing.
 pub fn run_commands(config: &LoopConfig, commands: &[DirCommand]) -> Result<()> {
-    let mut filtered: Vec<DirCommand> = commands.to_vec();
+    let config = &*stdout_report_config(config)?;
+    execute_commands_internal(config, &prepare_commands(config, commands)?)
+}
+
+/// Applies include/exclude filters and `rerun_failed` to a command list.
+fn prepare_commands(config: &LoopConfig, commands: &[DirCommand]) -> Result<Vec<DirCommand>> {
+    let mut filtered = filter_commands(config, commands.to_vec());
 
+    if config.rerun_failed {
+        history::retain_last_failures(config, &mut filtered)?;
+        if filtered.is_empty() && !config.silent && !config.json_output {
+            println!("No failed directories to rerun");
+        }
+    }
+
+    Ok(filtered)
+}
+
+/// Run pipelines of ordered steps, one per directory. Filters and
+/// `rerun_failed` apply as in [`run_commands`].
+pub fn run_pipelines(config: &LoopConfig, pipelines: &[Pipeline]) -> Result<()> {
+    let commands: Vec<DirCommand> = pipelines.iter().map(DirCommand::from).collect();
+    run_commands(config, &commands)
+}
+
+/// Run each command once per combination of the matrix axes, then print a
+/// directory × variant grid of outcomes. Filters and `rerun_failed` apply
+/// as in [`run_commands`].
+pub fn run_matrix(
+    config: &LoopConfig,
+    commands: &[DirCommand],
+    axes: &[matrix::MatrixAxis],
+) -> Result<()> {
+    let config = &*stdout_report_config(config)?;
+    // Expand first so `rerun_failed` can match failed variants
+    let commands = prepare_commands(config, &matrix::expand_matrix(commands, axes))?;
+    let results = execute_and_report(config, &commands)?;
+    if !config.silent && !config.json_output && !config.dry_run && !results.is_empty() {
+        println!("{}", matrix::render_grid(&results));
+    }
+    check_failures(config, &results)
+}
+
+/// Applies the include/exclude filters from config to a command list.
+fn filter_commands(config: &LoopConfig, mut commands: Vec<DirCommand>) -> Vec<DirCommand> {
     if let Some(ref includes) = config.include_filters {
         if !includes.is_empty() {
-            filtered.retain(|c| includes.iter().any(|f| c.dir.contains(f)));
+            commands.retain(|c| includes.iter().any(|f| c.dir.contains(f)));
         }
     }
 
     if let Some(ref excludes) = config.exclude_filters {
         if !excludes.is_empty() {
-            filtered.retain(|c| {
+            if config.verbose {
+                println!("Exclude filters: {excludes:?}");
+            }
+            commands.retain(|c| {
                 let excluded = excludes.iter().any(|f| {
                     let f = f.trim_end_matches('/');
                     c.dir.contains(f)
                 });
+                if config.verbose {
+                    println!("Dir: {}, excluded: {excluded}", c.dir);
+                }
                 !excluded
             });
         }
     }
 
-    execute_commands_internal(config, &filtered)
+    commands
 }
 
 pub fn should_ignore(path: &Path, ignore: &[String]) -> bool {
@@ -0,0 +1,136 @@
+//! Matrix execution: one command run across combinations of env variants.
+
+use crate::{CommandResult, DirCommand};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A named dimension of the matrix, e.g. `log` with `debug` and `info`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MatrixAxis {
+    pub name: String,
+    pub values: Vec<MatrixValue>,
+}
+
+/// One value of an axis: a label and the env vars it sets.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MatrixValue {
+    pub label: String,
+    pub env: HashMap<String, String>,
+}
+
+impl MatrixAxis {
+    /// An axis setting the single variable `var`, labelled by its values.
+    pub fn env_var(name: &str, var: &str, values: &[&str]) -> Self {
+        MatrixAxis {
+            name: name.to_string(),
+            values: values
+                .iter()
+                .map(|value| MatrixValue {
+                    label: value.to_string(),
+                    env: HashMap::from([(var.to_string(), value.to_string())]),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Expands each command into one command per combination of axis values
+/// (the first axis varying slowest). Variant env vars override the
+/// command's own, and each command is labelled `name=label, ...`. Without
+/// axes the commands are returned unchanged.
+pub fn expand_matrix(commands: &[DirCommand], axes: &[MatrixAxis]) -> Vec<DirCommand> {
+    if axes.is_empty() {
+        return commands.to_vec();
+    }
+    let variants = variants(axes);
+    commands
+        .iter()
+        .flat_map(|command| {
+            variants.iter().map(move |(label, env)| {
+                let mut merged = command.env.clone().unwrap_or_default();
+                merged.extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
+                DirCommand {
+                    env: Some(merged),
+                    variant: Some(label.clone()),
+                    ..command.clone()
+                }
+            })
+        })
+        .collect()
+}
+
+/// Every combination of axis values as (label, env).
+fn variants(axes: &[MatrixAxis]) -> Vec<(String, HashMap<String, String>)> {
+    let mut variants = vec![(Vec::new(), HashMap::new())];
+    for axis in axes {
+        variants = variants
+            .into_iter()
+            .flat_map(|(labels, env): (Vec<String>, HashMap<String, String>)| {
+                axis.values.iter().map(move |value| {
+                    let mut labels = labels.clone();
+                    labels.push(format!("{}={}", axis.name, value.label));
+                    let mut env = env.clone();
+                    env.extend(value.env.clone());
+                    (labels, env)
+                })
+            })
+            .collect();
+    }
+    variants
+        .into_iter()
+        .map(|(labels, env)| (labels.join(", "), env))
+        .collect()
+}
+
+/// Renders a directory × variant grid of outcomes: `✓` passed, `✗` failed,
+/// `-` skipped or not run. Rows and columns keep first-seen order.
+pub fn render_grid(results: &[CommandResult]) -> String {
+    let mut rows: Vec<&Path> = Vec::new();
+    let mut columns: Vec<&str> = Vec::new();
+    for result in results {
+        if !rows.contains(&result.directory.as_path()) {
+            rows.push(&result.directory);
+        }
+        let variant = result.variant.as_deref().unwrap_or("");
+        if !columns.contains(&variant) {
+            columns.push(variant);
+        }
+    }
+
+    let row_label = |dir: &Path| {
+        dir.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(".")
+            .to_string()
+    };
+    let label_width = rows
+        .iter()
+        .map(|dir| row_label(dir).chars().count())
+        .max()
+        .unwrap_or(0);
+    let widths: Vec<usize> = columns.iter().map(|c| c.chars().count().max(1)).collect();
+
+    let mut header = format!("{:label_width$}", "");
+    for (column, width) in columns.iter().zip(&widths) {
+        header.push_str(&format!("  {column:width$}"));
+    }
+    let mut out = format!("{}\n", header.trim_end());
+    for dir in rows {
+        let mut line = format!("{:label_width$}", row_label(dir));
+        for (column, width) in columns.iter().zip(&widths) {
+            let cell = results
+                .iter()
+                .find(|r| r.directory == dir && r.variant.as_deref().unwrap_or("") == *column)
+                .map_or("-", |r| match (r.skipped, r.success) {
+                    (true, _) => "-",
+                    (false, true) => "✓",
+                    (false, false) => "✗",
+                });
+            line.push_str(&format!("  {cell:width$}"));
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
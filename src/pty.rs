@@ -0,0 +1,150 @@
+//! Running captured commands under a pseudo-terminal (Linux only).
+
+use crate::capture::{capture_stream, write_stdin, CapturedStream};
+use crate::redact::Redactor;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+const DEFAULT_WINDOW_SIZE: libc::winsize = libc::winsize {
+    ws_row: 24,
+    ws_col: 80,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+};
+
+/// Runs `cmd` with stdin, stdout and stderr attached to a new PTY and
+/// captures everything the child writes to it. A terminal merges stdout and
+/// stderr, so all output is returned as stdout. When `input` is given it is
+/// piped to stdin instead, as with `cmd < file` in a terminal; without it
+/// stdin is null, so commands that read stdin see EOF instead of waiting on
+/// a terminal nobody types into.
+pub(crate) fn run_in_pty(
+    cmd: &mut Command,
+    input: Option<&[u8]>,
+    limit: Option<usize>,
+    label: &str,
+    redactor: &Redactor,
+) -> io::Result<(ExitStatus, CapturedStream)> {
+    let (master, slave) = open_pty()?;
+
+    cmd.stdin(if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    })
+    .stdout(Stdio::from(slave.try_clone()?))
+    .stderr(Stdio::from(slave));
+    // SAFETY: only async-signal-safe calls between fork and exec
+    unsafe {
+        cmd.pre_exec(|| {
+            // New session with the PTY as controlling terminal, so the child
+            // gets job control and `isatty` behaves as in a real terminal.
+            // stdout is always the slave; stdin never is.
+            if libc::setsid() == -1 || libc::ioctl(1, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let spawned = cmd.spawn();
+    // Drop the command's copies of the slave side; reads from the master
+    // only end once every slave descriptor is closed
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let mut child = spawned?;
+
+    let stdin = child.stdin.take();
+    let reader = master.try_clone()?;
+    let captured = thread::scope(|s| {
+        if let (Some(stdin), Some(input)) = (stdin, input) {
+            s.spawn(move || write_stdin(stdin, input));
+        }
+        capture_stream(PtyReader(File::from(reader)), limit, label, redactor)
+    });
+    // Output ends when the child closes its terminal descriptors, which may
+    // be before it exits. Closing the master then would hang up the terminal
+    // and kill the child with SIGHUP, so keep it open until the child is gone.
+    let status = child.wait()?;
+    drop(master);
+    Ok((status, captured?))
+}
+
+fn open_pty() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut size = DEFAULT_WINDOW_SIZE;
+    let stdout = io::stdout();
+    if stdout.is_terminal() {
+        // SAFETY: TIOCGWINSZ writes a winsize into the provided struct
+        let mut current: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(stdout.as_raw_fd(), libc::TIOCGWINSZ, &mut current) } == 0
+            && current.ws_col > 0
+        {
+            size = current;
+        }
+    }
+
+    // Open both ends close-on-exec from the start, so unrelated children
+    // spawned concurrently never inherit them; the slave still reaches our
+    // child through its dup'ed stdio
+    // SAFETY: posix_openpt returns a new descriptor on success
+    let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if master == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just opened and is owned by us
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+
+    let mut name = [0 as libc::c_char; 128];
+    // SAFETY: plain calls on a descriptor we own; ptsname_r writes a
+    // NUL-terminated path into `name` of the given length
+    let rc = unsafe {
+        if libc::grantpt(master.as_raw_fd()) != 0 || libc::unlockpt(master.as_raw_fd()) != 0 {
+            -1
+        } else {
+            libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len())
+        }
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `name` holds the NUL-terminated slave path
+    let slave = unsafe {
+        libc::open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        )
+    };
+    if slave == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just opened and is owned by us
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+
+    // SAFETY: plain ioctl/termios calls on descriptors we own
+    unsafe {
+        libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &size);
+        // Leave newlines alone so captured output matches piped output
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+            termios.c_oflag &= !libc::ONLCR;
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+        }
+    }
+    Ok((master, slave))
+}
+
+/// Reads the PTY master, treating `EIO` (all slave ends closed) as EOF.
+struct PtyReader(File);
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            other => other,
+        }
+    }
+}
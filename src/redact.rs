@@ -0,0 +1,107 @@
+//! Masking of secrets in captured output and displayed commands.
+
+use crate::LoopConfig;
+use anyhow::{Context, Result};
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+
+/// Replacement text for redacted content.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Masks the values of configured env vars and matches of configured regex
+/// patterns.
+#[derive(Debug, Default)]
+pub struct Redactor {
+    literals: Vec<Vec<u8>>,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// Builds a redactor from `redact_env` and `redact_patterns`. Env values
+    /// are looked up in `extra_env` (the executors pass the command's full
+    /// environment, including `.env` files), then `config.env`, then the
+    /// parent environment; unset or empty variables are ignored.
+    pub fn from_config(
+        config: &LoopConfig,
+        extra_env: Option<&HashMap<String, String>>,
+    ) -> Result<Self> {
+        let mut literals: Vec<Vec<u8>> = config
+            .redact_env
+            .iter()
+            .filter_map(|name| {
+                extra_env
+                    .and_then(|e| e.get(name))
+                    .or_else(|| config.env.as_ref().and_then(|e| e.get(name)))
+                    .cloned()
+                    .or_else(|| env::var(name).ok())
+            })
+            .filter(|value| !value.is_empty())
+            .map(String::into_bytes)
+            .collect();
+        // Longest first so a secret containing another is masked whole
+        literals.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        literals.dedup();
+
+        let patterns = config
+            .redact_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).with_context(|| format!("Invalid redaction pattern: {pattern}"))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Redactor { literals, patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.literals.is_empty() && self.patterns.is_empty()
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self.redact_bytes(text.as_bytes()) {
+            Cow::Borrowed(_) => Cow::Borrowed(text),
+            // Whole UTF-8 matches replaced with UTF-8 text stay valid
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+
+    /// Redacts raw output, which need not be valid UTF-8.
+    pub fn redact_bytes<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut bytes = Cow::Borrowed(bytes);
+        for literal in &self.literals {
+            if let Some(replaced) = replace_literal(&bytes, literal) {
+                bytes = Cow::Owned(replaced);
+            }
+        }
+        for pattern in &self.patterns {
+            if let Cow::Owned(replaced) = pattern.replace_all(&bytes, REDACTED.as_bytes()) {
+                bytes = Cow::Owned(replaced);
+            }
+        }
+        bytes
+    }
+}
+
+/// Replaces every occurrence of `needle`, or returns `None` if there is none.
+fn replace_literal(haystack: &[u8], needle: &[u8]) -> Option<Vec<u8>> {
+    let mut out: Option<Vec<u8>> = None;
+    let mut copied = 0;
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if &haystack[i..i + needle.len()] == needle {
+            let out = out.get_or_insert_with(|| Vec::with_capacity(haystack.len()));
+            out.extend_from_slice(&haystack[copied..i]);
+            out.extend_from_slice(REDACTED.as_bytes());
+            i += needle.len();
+            copied = i;
+        } else {
+            i += 1;
+        }
+    }
+    out.map(|mut out| {
+        out.extend_from_slice(&haystack[copied..]);
+        out
+    })
+}
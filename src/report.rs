@@ -0,0 +1,468 @@
+//! Report files generated from the results of a run.
+
+use crate::CommandResult;
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+/// Removes terminal escape sequences (colors, cursor movement) from `text`.
+pub(crate) fn strip_ansi(text: &str) -> Cow<'_, str> {
+    static ANSI: OnceLock<Regex> = OnceLock::new();
+    ANSI.get_or_init(|| Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]").unwrap())
+        .replace_all(text, "")
+}
+
+/// `N commands: P passed, F failed[, S skipped][, C cached]`
+pub(crate) fn totals(results: &[CommandResult]) -> String {
+    let failed = results.iter().filter(|r| !r.success).count();
+    let skipped = results.iter().filter(|r| r.skipped).count();
+    let cached = results.iter().filter(|r| r.cached).count();
+    let mut totals = format!(
+        "{} commands: {} passed, {failed} failed",
+        results.len(),
+        results.len() - failed - skipped
+    );
+    if skipped > 0 {
+        totals.push_str(&format!(", {skipped} skipped"));
+    }
+    if cached > 0 {
+        totals.push_str(&format!(", {cached} cached"));
+    }
+    totals
+}
+
+/// Name of a result in reports: its directory, with the matrix variant.
+fn test_name(result: &CommandResult) -> String {
+    match &result.variant {
+        Some(variant) => format!("{} [{variant}]", result.directory.display()),
+        None => result.directory.display().to_string(),
+    }
+}
+
+/// Renders a JUnit XML report with one test case per directory. Failures
+/// carry the exit code and stderr; stdout goes to `system-out`.
+pub fn junit_xml(results: &[CommandResult]) -> String {
+    let failures = results.iter().filter(|r| !r.success).count();
+    let skipped = results.iter().filter(|r| r.skipped).count();
+    let time: f64 = results.iter().map(|r| r.duration.as_secs_f64()).sum();
+    let counts = format!(
+        "tests=\"{}\" failures=\"{failures}\" errors=\"0\" skipped=\"{skipped}\" time=\"{time:.3}\"",
+        results.len()
+    );
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!("<testsuites name=\"loop\" {counts}>\n"));
+    xml.push_str(&format!("  <testsuite name=\"loop\" {counts}>\n"));
+    for result in results {
+        xml.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"loop\" time=\"{:.3}\">\n",
+            xml_escape(&test_name(result)),
+            result.duration.as_secs_f64()
+        ));
+        if result.skipped {
+            xml.push_str("      <skipped/>\n");
+        } else if !result.success {
+            xml.push_str(&format!(
+                "      <failure message=\"Exit code {code}\" type=\"exit code {code}\">{}</failure>\n",
+                xml_escape(&result.stderr),
+                code = result.exit_code
+            ));
+        }
+        if !result.stdout.is_empty() {
+            xml.push_str(&format!(
+                "      <system-out>{}</system-out>\n",
+                xml_escape(&result.stdout)
+            ));
+        }
+        if !result.stderr.is_empty() {
+            xml.push_str(&format!(
+                "      <system-err>{}</system-err>\n",
+                xml_escape(&result.stderr)
+            ));
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Escapes `text` for XML content and attributes, dropping escape sequences
+/// and characters XML can't represent.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in strip_ansi(text).chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders a TAP version 13 report with one test point per directory.
+/// Failures carry a YAML block with the exit code and output.
+pub fn tap(results: &[CommandResult]) -> String {
+    let mut tap = format!("TAP version 13\n1..{}\n", results.len());
+    for (i, result) in results.iter().enumerate() {
+        // `#` starts a directive in a description
+        let name = test_name(result).replace('#', "\\#");
+        if result.skipped {
+            tap.push_str(&format!("ok {} - {name} # SKIP skipped\n", i + 1));
+        } else if result.success {
+            let cached = if result.cached { " (cached)" } else { "" };
+            tap.push_str(&format!("ok {} - {name}{cached}\n", i + 1));
+        } else {
+            tap.push_str(&format!("not ok {} - {name}\n", i + 1));
+            tap.push_str("  ---\n");
+            tap.push_str(&format!("  exit_code: {}\n", result.exit_code));
+            tap.push_str(&format!("  duration_ms: {}\n", result.duration.as_millis()));
+            for (key, output) in [("stdout", &result.stdout), ("stderr", &result.stderr)] {
+                if !output.trim().is_empty() {
+                    tap.push_str(&format!("  {key}: |\n"));
+                    for line in strip_ansi(output).lines() {
+                        tap.push_str(format!("    {line}").trim_end());
+                        tap.push('\n');
+                    }
+                }
+            }
+            tap.push_str("  ...\n");
+        }
+    }
+    tap
+}
+
+/// Renders a Markdown report: a summary table of all directories followed
+/// by collapsed output sections for the failures.
+pub fn markdown(results: &[CommandResult]) -> String {
+    let mut md = format!("## loop: {}\n\n", totals(results));
+    md.push_str("| Directory | Status | Exit code | Duration |\n");
+    md.push_str("| --- | --- | --- | --- |\n");
+    for result in results {
+        let (status, exit_code) = if result.skipped {
+            ("⏭️ skipped", "-".to_string())
+        } else if !result.success {
+            ("❌ failed", result.exit_code.to_string())
+        } else if result.cached {
+            ("✅ cached", "-".to_string())
+        } else {
+            ("✅ passed", result.exit_code.to_string())
+        };
+        md.push_str(&format!(
+            "| `{}` | {status} | {exit_code} | {:.1}s |\n",
+            test_name(result).replace('|', "\\|").replace('`', "'"),
+            result.duration.as_secs_f64()
+        ));
+    }
+
+    let failures: Vec<&CommandResult> = results.iter().filter(|r| !r.success).collect();
+    if !failures.is_empty() {
+        md.push_str("\n### Failures\n");
+    }
+    for result in failures {
+        md.push_str(&format!(
+            "\n<details>\n<summary><code>{}</code>: exit code {}</summary>\n\n",
+            html_escape(&test_name(result)),
+            result.exit_code
+        ));
+        let output = strip_ansi(&format!("{}{}", result.stdout, result.stderr)).into_owned();
+        if output.trim().is_empty() {
+            md.push_str("No output.\n");
+        } else {
+            // A fence longer than any backtick run in the output
+            let longest = output.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+            let fence = "`".repeat(longest.max(2) + 1);
+            md.push_str(&format!("{fence}\n{}\n{fence}\n", output.trim_end()));
+        }
+        md.push_str("\n</details>\n");
+    }
+    md
+}
+
+/// Escapes `text` for HTML content.
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders a self-contained HTML report: a summary, a filter by name and
+/// status, and one expandable entry per directory with its status badge,
+/// duration and stdout/stderr (terminal colors preserved).
+pub fn html(results: &[CommandResult]) -> String {
+    let mut html = String::from(HTML_HEAD);
+    html.push_str(&format!(
+        "<h1>loop run</h1>\n<p class=\"totals\">{}</p>\n",
+        html_escape(&totals(results))
+    ));
+    html.push_str(HTML_FILTERS);
+    html.push_str("<div id=\"results\">\n");
+    for result in results {
+        let status = if result.skipped {
+            "skipped"
+        } else if !result.success {
+            "failed"
+        } else if result.cached {
+            "cached"
+        } else {
+            "passed"
+        };
+        let name = html_escape(&test_name(result));
+        // Failures start expanded
+        let open = if result.success { "" } else { " open" };
+        html.push_str(&format!(
+            "<details class=\"result\" data-status=\"{status}\" data-name=\"{name}\"{open}>\n"
+        ));
+        html.push_str(&format!(
+            "<summary><span class=\"badge {status}\">{status}</span> \
+             <code>{name}</code> <span class=\"meta\">{:.1}s",
+            result.duration.as_secs_f64()
+        ));
+        if !result.skipped && !result.cached {
+            html.push_str(&format!(", exit code {}", result.exit_code));
+        }
+        html.push_str("</span></summary>\n");
+        html.push_str(&format!(
+            "<p class=\"command\"><code>$ {}</code></p>\n",
+            html_escape(&result.command)
+        ));
+        for (label, output) in [("stdout", &result.stdout), ("stderr", &result.stderr)] {
+            if !output.is_empty() {
+                html.push_str(&format!(
+                    "<h3>{label}</h3>\n<pre class=\"{label}\">{}</pre>\n",
+                    ansi_to_html(output)
+                ));
+            }
+        }
+        if result.stdout.is_empty() && result.stderr.is_empty() {
+            html.push_str("<p class=\"meta\">No output.</p>\n");
+        }
+        html.push_str("</details>\n");
+    }
+    html.push_str("</div>\n");
+    html.push_str(HTML_SCRIPT);
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>loop run</title>
+<style>
+body { font-family: system-ui, sans-serif; margin: 2em; color: #24292f; }
+.filters { display: flex; gap: 0.5em; margin-bottom: 1em; }
+.filters input { flex: 1; padding: 0.3em; }
+.result { border: 1px solid #d0d7de; border-radius: 6px; margin: 0.3em 0; padding: 0.3em 0.6em; }
+.result summary { cursor: pointer; }
+.badge { display: inline-block; min-width: 4.5em; text-align: center; border-radius: 1em; padding: 0 0.5em; color: #fff; font-size: 0.85em; }
+.badge.passed { background: #1a7f37; }
+.badge.failed { background: #cf222e; }
+.badge.skipped { background: #9a6700; }
+.badge.cached { background: #0969da; }
+.meta { color: #57606a; }
+pre { background: #0d1117; color: #e6edf3; padding: 0.6em; overflow-x: auto; border-radius: 6px; }
+h3 { font-size: 0.9em; margin: 0.5em 0 0; }
+</style>
+</head>
+<body>
+"#;
+
+const HTML_FILTERS: &str = r#"<div class="filters">
+<input id="filter" type="search" placeholder="Filter directories">
+<select id="status">
+<option value="">All</option>
+<option value="passed">Passed</option>
+<option value="failed">Failed</option>
+<option value="skipped">Skipped</option>
+<option value="cached">Cached</option>
+</select>
+</div>
+"#;
+
+const HTML_SCRIPT: &str = r#"<script>
+const filter = document.getElementById("filter");
+const status = document.getElementById("status");
+function apply() {
+  const text = filter.value.toLowerCase();
+  for (const result of document.querySelectorAll(".result")) {
+    const matches = result.dataset.name.toLowerCase().includes(text)
+      && (!status.value || result.dataset.status === status.value);
+    result.hidden = !matches;
+  }
+}
+filter.addEventListener("input", apply);
+status.addEventListener("change", apply);
+</script>
+"#;
+
+/// Converts terminal output to HTML, turning SGR color and style sequences
+/// into styled spans and dropping other escape sequences.
+pub fn ansi_to_html(text: &str) -> String {
+    static SEQUENCE: OnceLock<Regex> = OnceLock::new();
+    let sequence = SEQUENCE.get_or_init(|| Regex::new(r"\x1b\[([0-9;?]*)([ -/]*[@-~])").unwrap());
+
+    let mut html = String::new();
+    let mut style = SgrStyle::default();
+    let mut span_open = false;
+    let mut last = 0;
+    for captures in sequence.captures_iter(text) {
+        let whole = captures.get(0).unwrap();
+        html.push_str(&html_escape_text(&text[last..whole.start()]));
+        last = whole.end();
+        if &captures[2] != "m" {
+            continue;
+        }
+        style.apply(&captures[1]);
+        if span_open {
+            html.push_str("</span>");
+            span_open = false;
+        }
+        let css = style.css();
+        if !css.is_empty() {
+            html.push_str(&format!("<span style=\"{css}\">"));
+            span_open = true;
+        }
+    }
+    html.push_str(&html_escape_text(&text[last..]));
+    if span_open {
+        html.push_str("</span>");
+    }
+    html
+}
+
+/// Escapes output text for HTML, dropping stray control characters.
+fn html_escape_text(text: &str) -> String {
+    html_escape(text)
+        .chars()
+        .filter(|&c| !c.is_control() || matches!(c, '\t' | '\n'))
+        .collect()
+}
+
+/// Text attributes set by SGR escape sequences.
+#[derive(Debug, Default)]
+struct SgrStyle {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    foreground: Option<String>,
+    background: Option<String>,
+}
+
+/// The 16 standard terminal colors.
+const ANSI_COLORS: [&str; 16] = [
+    "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+    "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+];
+
+impl SgrStyle {
+    /// Applies the `;`-separated parameters of one SGR sequence.
+    fn apply(&mut self, params: &str) {
+        let codes: Vec<u32> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                0 => *self = SgrStyle::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                code @ 30..=37 => self.foreground = Some(ANSI_COLORS[(code - 30) as usize].into()),
+                code @ 90..=97 => {
+                    self.foreground = Some(ANSI_COLORS[(code - 90 + 8) as usize].into())
+                }
+                39 => self.foreground = None,
+                code @ 40..=47 => self.background = Some(ANSI_COLORS[(code - 40) as usize].into()),
+                code @ 100..=107 => {
+                    self.background = Some(ANSI_COLORS[(code - 100 + 8) as usize].into())
+                }
+                49 => self.background = None,
+                code @ (38 | 48) => {
+                    let (color, used) = extended_color(&codes[i + 1..]);
+                    i += used;
+                    if code == 38 {
+                        self.foreground = color;
+                    } else {
+                        self.background = color;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn css(&self) -> String {
+        let mut css = Vec::new();
+        if let Some(color) = &self.foreground {
+            css.push(format!("color:{color}"));
+        }
+        if let Some(color) = &self.background {
+            css.push(format!("background-color:{color}"));
+        }
+        if self.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if self.dim {
+            css.push("opacity:0.7".to_string());
+        }
+        if self.italic {
+            css.push("font-style:italic".to_string());
+        }
+        if self.underline {
+            css.push("text-decoration:underline".to_string());
+        }
+        css.join(";")
+    }
+}
+
+/// Parses the parameters after 38/48: `5;N` (256-color palette) or
+/// `2;R;G;B`. Returns the color and how many parameters were used.
+fn extended_color(params: &[u32]) -> (Option<String>, usize) {
+    match params {
+        [5, n, ..] => {
+            let n = *n as usize;
+            let color = match n {
+                0..=15 => ANSI_COLORS[n].to_string(),
+                16..=231 => {
+                    let level = |v: usize| if v == 0 { 0 } else { 55 + v * 40 };
+                    let n = n - 16;
+                    format!(
+                        "#{:02x}{:02x}{:02x}",
+                        level(n / 36),
+                        level(n / 6 % 6),
+                        level(n % 6)
+                    )
+                }
+                232..=255 => {
+                    let gray = 8 + (n - 232) * 10;
+                    format!("#{gray:02x}{gray:02x}{gray:02x}")
+                }
+                _ => return (None, 2),
+            };
+            (Some(color), 2)
+        }
+        [2, r, g, b, ..] => (
+            Some(format!(
+                "#{:02x}{:02x}{:02x}",
+                r.min(&255),
+                g.min(&255),
+                b.min(&255)
+            )),
+            4,
+        ),
+        [] => (None, 0),
+        _ => (None, 1),
+    }
+}
@@ -0,0 +1,185 @@
+//! Scheduling of parallel commands under per-resource concurrency limits.
+
+use crate::{history, DirCommand, LoopConfig, ScheduleStrategy};
+use anyhow::Result;
+use colored::*;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Hands out commands to worker threads so that no resource key is held by
+/// more commands than its limit, and no more than the current overall limit
+/// run at once. Commands are started in list order (or the order given to
+/// [`Scheduler::with_order`]), except that a command waiting on a busy
+/// resource lets later ones go first.
+pub(crate) struct Scheduler<'a> {
+    /// Limited resource keys of each command, deduplicated.
+    resources: Vec<Vec<&'a str>>,
+    limits: &'a HashMap<String, usize>,
+    state: Mutex<State<'a>>,
+    changed: Condvar,
+}
+
+struct State<'a> {
+    pending: Vec<usize>,
+    in_use: HashMap<&'a str, usize>,
+    running: usize,
+    limit: usize,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(commands: &'a [DirCommand], limits: &'a HashMap<String, usize>) -> Result<Self> {
+        if let Some((key, _)) = limits.iter().find(|(_, &limit)| limit == 0) {
+            anyhow::bail!("Resource limit for '{key}' must be at least 1");
+        }
+        let resources = commands
+            .iter()
+            .map(|c| {
+                let mut keys: Vec<&str> = c
+                    .resources
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|key| limits.contains_key(*key))
+                    .collect();
+                keys.sort_unstable();
+                keys.dedup();
+                keys
+            })
+            .collect();
+        Ok(Scheduler {
+            resources,
+            limits,
+            state: Mutex::new(State {
+                pending: (0..commands.len()).collect(),
+                in_use: HashMap::new(),
+                running: 0,
+                limit: usize::MAX,
+            }),
+            changed: Condvar::new(),
+        })
+    }
+
+    /// Starts commands in `order` (indices into the command list) instead of
+    /// list order.
+    pub fn with_order(self, order: Vec<usize>) -> Self {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).pending = order;
+        self
+    }
+
+    /// Sets how many commands may run at once. Commands already running are
+    /// not interrupted when the limit drops.
+    pub fn set_limit(&self, limit: usize) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).limit = limit.max(1);
+        self.changed.notify_all();
+    }
+
+    /// Blocks until a pending command can start within every limit, reserves
+    /// its resources and returns its index. Returns `None` once no commands
+    /// are pending.
+    pub fn next(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if state.pending.is_empty() {
+                return None;
+            }
+            let ready = if state.running < state.limit {
+                state.pending.iter().position(|&i| {
+                    self.resources[i]
+                        .iter()
+                        .all(|key| state.in_use.get(key).copied().unwrap_or(0) < self.limits[*key])
+                })
+            } else {
+                None
+            };
+            if let Some(pos) = ready {
+                let index = state.pending.remove(pos);
+                state.running += 1;
+                for key in &self.resources[index] {
+                    *state.in_use.entry(key).or_insert(0) += 1;
+                }
+                return Some(index);
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Releases the resources reserved for the command at `index`.
+    pub fn finish(&self, index: usize) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.running -= 1;
+        for key in &self.resources[index] {
+            if let Some(count) = state.in_use.get_mut(key) {
+                *count -= 1;
+            }
+        }
+        drop(state);
+        self.changed.notify_all();
+    }
+}
+
+/// Returns the order in which to start `commands` under `config.schedule`.
+/// Sorts are stable, so ties keep list order.
+pub(crate) fn schedule_order(config: &LoopConfig, commands: &[DirCommand]) -> Result<Vec<usize>> {
+    let mut order: Vec<usize> = (0..commands.len()).collect();
+    match config.schedule {
+        ScheduleStrategy::ListOrder => {}
+        ScheduleStrategy::Priority => {
+            order.sort_by_key(|&i| std::cmp::Reverse(commands[i].priority));
+        }
+        ScheduleStrategy::LongestFirst => {
+            let durations =
+                match history::history_dir(config).map(|dir| history::last_durations(&dir)) {
+                    Some(Ok(durations)) => durations,
+                    // The order is only a hint; an unreadable history must not
+                    // stop the run
+                    Some(Err(e)) => {
+                        if !config.silent {
+                            eprintln!(
+                                "{} Starting in list order, run history unavailable: {e:#}",
+                                "warning:".yellow()
+                            );
+                        }
+                        return Ok(order);
+                    }
+                    None => HashMap::new(),
+                };
+            // Directories without a recorded duration may be slow; start them
+            // first along with the longest
+            order.sort_by_key(|&i| {
+                std::cmp::Reverse(
+                    durations
+                        .get(&commands[i].dir)
+                        .copied()
+                        .map_or(u128::MAX, |d| d.as_millis()),
+                )
+            });
+        }
+        ScheduleStrategy::Shuffle => {
+            let seed = config.shuffle_seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64)
+                    ^ u64::from(std::process::id())
+            });
+            shuffle(&mut order, seed);
+        }
+    }
+    Ok(order)
+}
+
+/// Fisher-Yates shuffle driven by xorshift64*, so a seed reproduces the
+/// same order on every platform.
+fn shuffle(items: &mut [usize], seed: u64) {
+    // xorshift needs a non-zero state
+    let mut state = seed | 1;
+    let mut next = || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    };
+    for i in (1..items.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
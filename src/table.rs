@@ -0,0 +1,170 @@
+//! Table summary: one row per directory with its status, exit code,
+//! duration and an excerpt of its output.
+
+use crate::report::{strip_ansi, totals};
+use crate::{group, CommandResult};
+use colored::*;
+use regex::Regex;
+
+const HEADERS: [&str; 5] = ["DIRECTORY", "STATUS", "EXIT", "DURATION", "OUTPUT"];
+
+/// Renders `results` as a column-aligned table followed by a totals line.
+/// The output column holds the first non-empty line of stdout (or stderr),
+/// or the match of `extract` (its first capture group if it has one). With
+/// a `width`, rows are truncated to fit; with `color`, statuses are
+/// colored and the header is bold.
+pub fn render_table(
+    results: &[CommandResult],
+    extract: Option<&Regex>,
+    width: Option<usize>,
+    color: bool,
+) -> String {
+    let rows: Vec<[String; 5]> = results
+        .iter()
+        .map(|r| {
+            [
+                group::label(r),
+                status(r).to_string(),
+                if r.skipped || r.cached {
+                    "-".to_string()
+                } else {
+                    r.exit_code.to_string()
+                },
+                format!("{:.1}s", r.duration.as_secs_f64()),
+                excerpt(r, extract),
+            ]
+        })
+        .collect();
+
+    let mut widths = HEADERS.map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    // Only the last column is cut to fit; it gets at least a few characters
+    let fixed: usize = widths[..4].iter().map(|w| w + 2).sum();
+    let output_width = width.map(|width| width.saturating_sub(fixed).max(8));
+
+    let line = |cells: [String; 5], status_color: Option<Color>| {
+        let mut line = String::new();
+        for (i, (cell, width)) in cells.iter().zip(widths).enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            match (i, status_color) {
+                (1, Some(c)) => line.push_str(&format!("{cell:width$}").color(c).to_string()),
+                (4, _) => match output_width {
+                    Some(max) => line.push_str(&truncate(cell, max)),
+                    None => line.push_str(cell),
+                },
+                _ => line.push_str(&format!("{cell:width$}")),
+            }
+        }
+        line.trim_end().to_string()
+    };
+
+    let mut out = String::new();
+    let header = line(HEADERS.map(String::from), None);
+    if color {
+        out.push_str(&header.bold().to_string());
+    } else {
+        out.push_str(&header);
+    }
+    out.push('\n');
+    for (row, result) in rows.into_iter().zip(results) {
+        let status_color = color.then(|| match status(result) {
+            "FAIL" => Color::Red,
+            "skipped" => Color::Yellow,
+            "cached" => Color::Cyan,
+            _ => Color::Green,
+        });
+        out.push_str(&line(row, status_color));
+        out.push('\n');
+    }
+    out.push_str(&totals(results));
+    out.push('\n');
+    out
+}
+
+fn status(result: &CommandResult) -> &'static str {
+    if result.skipped {
+        "skipped"
+    } else if !result.success {
+        "FAIL"
+    } else if result.cached {
+        "cached"
+    } else {
+        "ok"
+    }
+}
+
+/// The output excerpt shown for a result, without escape sequences.
+fn excerpt(result: &CommandResult, extract: Option<&Regex>) -> String {
+    let stdout = strip_ansi(&result.stdout);
+    let text = match extract {
+        Some(extract) => extract
+            .captures(&stdout)
+            .and_then(|c| c.get(1).or_else(|| c.get(0)))
+            .map_or("", |m| m.as_str())
+            .to_string(),
+        None => {
+            let stderr = strip_ansi(&result.stderr);
+            let first_line = |text: &str| {
+                text.lines()
+                    .map(str::trim)
+                    .find(|l| !l.is_empty())
+                    .map(str::to_string)
+            };
+            first_line(&stdout)
+                .or_else(|| first_line(&stderr))
+                .unwrap_or_default()
+        }
+    };
+    // Keep each row on one line
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Cuts `text` to `max` characters, ending in `…` when shortened.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(max.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+/// Width of the terminal on stdout: `COLUMNS` if set, otherwise the
+/// terminal's own size where it can be queried.
+pub(crate) fn terminal_width() -> Option<usize> {
+    if let Some(columns) = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|&c: &usize| c > 0)
+    {
+        return Some(columns);
+    }
+    window_columns()
+}
+
+#[cfg(unix)]
+fn window_columns() -> Option<usize> {
+    // SAFETY: winsize is plain data; TIOCGWINSZ fills it in
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: queries the size of the terminal on stdout, if any
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == -1 {
+        return None;
+    }
+    Some(size.ws_col as usize).filter(|&c| c > 0)
+}
+
+#[cfg(not(unix))]
+fn window_columns() -> Option<usize> {
+    None
+}
@@ -126,6 +126,7 @@ fn test_run() {
         env: None,
         max_parallel: None,
         root_dir: None,
+        ..Default::default()
     };
 
     let result = run(&config, "echo test");
@@ -198,6 +199,7 @@ fn test_run_without_looprc() {
         env: None,
         max_parallel: None,
         root_dir: None,
+        ..Default::default()
     };
 
     let result = run(&config, "echo test");
@@ -233,6 +235,7 @@ fn test_run_parallel() {
         env: None,
         max_parallel: None,
         root_dir: None,
+        ..Default::default()
     };
 
     let result = run(&config, "echo test");
@@ -347,6 +350,7 @@ fn test_include_filters() {
         env: None,
         max_parallel: None,
         root_dir: None,
+        ..Default::default()
     };
 
     // The run function should only execute on directories matching the filter
@@ -383,6 +387,7 @@ fn test_exclude_filters() {
         env: None,
         max_parallel: None,
         root_dir: None,
+        ..Default::default()
     };
 
     let result = run(&config, "echo test");
@@ -481,6 +486,7 @@ fn test_dir_command_struct() {
         dir: "/some/path".to_string(),
         cmd: "git status".to_string(),
         env: None,
+        ..Default::default()
     };
     assert_eq!(cmd.dir, "/some/path");
     assert_eq!(cmd.cmd, "git status");
@@ -513,11 +519,13 @@ fn test_run_commands_sequential() {
             dir: dir1.to_str().unwrap().to_string(),
             cmd: "echo test1".to_string(),
             env: None,
+            ..Default::default()
         },
         DirCommand {
             dir: dir2.to_str().unwrap().to_string(),
             cmd: "echo test2".to_string(),
             env: None,
+            ..Default::default()
         },
     ];
 
@@ -544,11 +552,13 @@ fn test_run_commands_parallel() {
             dir: dir1.to_str().unwrap().to_string(),
             cmd: "echo test1".to_string(),
             env: None,
+            ..Default::default()
         },
         DirCommand {
             dir: dir2.to_str().unwrap().to_string(),
             cmd: "echo test2".to_string(),
             env: None,
+            ..Default::default()
         },
     ];
 
@@ -577,11 +587,13 @@ fn test_run_commands_with_different_commands() {
             dir: dir1.to_str().unwrap().to_string(),
             cmd: "echo command1".to_string(),
             env: None,
+            ..Default::default()
         },
         DirCommand {
             dir: dir2.to_str().unwrap().to_string(),
             cmd: "echo command2".to_string(),
             env: None,
+            ..Default::default()
         },
     ];
 
@@ -607,6 +619,7 @@ fn test_run_commands_dry_run() {
         dir: temp_dir.path().to_str().unwrap().to_string(),
         cmd: touch_cmd(&marker_file),
         env: None,
+        ..Default::default()
     }];
 
     let result = run_commands(&config, &commands);
@@ -630,6 +643,7 @@ fn test_run_commands_failure_handling() {
         dir: dir1.to_str().unwrap().to_string(),
         cmd: FAIL_CMD.to_string(), // This command always fails
         env: None,
+        ..Default::default()
     }];
 
     let result = run_commands(&config, &commands);
@@ -650,6 +664,7 @@ fn test_run_commands_failure_in_dry_run_succeeds() {
         dir: temp_dir.path().to_str().unwrap().to_string(),
         cmd: FAIL_CMD.to_string(),
         env: None,
+        ..Default::default()
     }];
 
     let result = run_commands(&config, &commands);
@@ -669,13 +684,24 @@ fn test_json_output_structures() {
             command: "echo hello".to_string(),
             success: true,
             exit_code: 0,
+            duration_ms: 5,
+            skipped: false,
             stdout: "hello\n".to_string(),
             stderr: String::new(),
+            stdout_encoding: None,
+            stderr_encoding: None,
+            stdout_file: None,
+            stderr_file: None,
+            steps: vec![],
+            variant: None,
+            cached: false,
         }],
         summary: JsonSummary {
             total: 1,
             succeeded: 1,
             failed: 0,
+            skipped: 0,
+            cached: 0,
             dry_run: false,
         },
     };
@@ -693,8 +719,17 @@ fn test_json_output_skips_empty_strings() {
         command: "echo".to_string(),
         success: true,
         exit_code: 0,
+        duration_ms: 0,
+        skipped: false,
         stdout: String::new(),
         stderr: String::new(),
+        stdout_encoding: None,
+        stderr_encoding: None,
+        stdout_file: None,
+        stderr_file: None,
+        steps: vec![],
+        variant: None,
+        cached: false,
     };
 
     let json = serde_json::to_string(&result).unwrap();
@@ -754,6 +789,7 @@ fn test_dir_command_serialization() {
         dir: "/path/to/dir".to_string(),
         cmd: "git status".to_string(),
         env: None,
+        ..Default::default()
     };
 
     let json = serde_json::to_string(&cmd).unwrap();
@@ -765,3 +801,2285 @@ fn test_dir_command_serialization() {
     assert_eq!(parsed.dir, "/path/to/dir");
     assert_eq!(parsed.cmd, "git status");
 }
+
+// ============================================================================
+// Tests for rerunning failures from the last run
+// ============================================================================
+
+#[test]
+fn test_last_run_is_recorded_under_root_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir1 = temp_dir.path().join("dir1");
+    fs::create_dir(&dir1).unwrap();
+
+    let config = LoopConfig {
+        silent: true,
+        root_dir: Some(temp_dir.path().to_path_buf()),
+        ..Default::default()
+    };
+    let commands = vec![DirCommand {
+        dir: dir1.to_str().unwrap().to_string(),
+        cmd: FAIL_CMD.to_string(),
+        env: None,
+        ..Default::default()
+    }];
+    assert!(run_commands(&config, &commands).is_err());
+
+    let history_dir = temp_dir.path().join(history::STATE_DIR_NAME);
+    let last_run = history::load_last_run(&history_dir).unwrap().unwrap();
+    assert_eq!(last_run.results.len(), 1);
+    assert_eq!(last_run.results[0].exit_code, 1);
+    assert_eq!(last_run.failures().count(), 1);
+
+    let failures = history::last_run_failures(&config).unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].dir, dir1.to_str().unwrap());
+    assert_eq!(failures[0].cmd, FAIL_CMD);
+}
+
+#[test]
+fn test_rerun_failed_only_runs_last_failures() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir1 = temp_dir.path().join("dir1");
+    let dir2 = temp_dir.path().join("dir2");
+    fs::create_dir(&dir1).unwrap();
+    fs::create_dir(&dir2).unwrap();
+
+    let mut config = LoopConfig {
+        silent: true,
+        history_dir: Some(temp_dir.path().join("history")),
+        ..Default::default()
+    };
+    let first_run = vec![
+        DirCommand {
+            dir: dir1.to_str().unwrap().to_string(),
+            cmd: "echo ok".to_string(),
+            env: None,
+            ..Default::default()
+        },
+        DirCommand {
+            dir: dir2.to_str().unwrap().to_string(),
+            cmd: FAIL_CMD.to_string(),
+            env: None,
+            ..Default::default()
+        },
+    ];
+    assert!(run_commands(&config, &first_run).is_err());
+
+    config.rerun_failed = true;
+    let retry = vec![
+        DirCommand {
+            dir: dir1.to_str().unwrap().to_string(),
+            cmd: touch_cmd(&dir1.join("marker")),
+            env: None,
+            ..Default::default()
+        },
+        DirCommand {
+            dir: dir2.to_str().unwrap().to_string(),
+            cmd: touch_cmd(&dir2.join("marker")),
+            env: None,
+            ..Default::default()
+        },
+    ];
+    assert!(run_commands(&config, &retry).is_ok());
+    assert!(!dir1.join("marker").exists());
+    assert!(dir2.join("marker").exists());
+
+    // The retry succeeded, so there is nothing left to rerun
+    assert!(history::last_run_failures(&config).unwrap().is_empty());
+}
+
+#[test]
+fn test_rerun_failed_without_history_dir_errors() {
+    let config = LoopConfig {
+        silent: true,
+        rerun_failed: true,
+        ..Default::default()
+    };
+    let result = run(&config, "echo test");
+    assert!(result.is_err());
+}
+
+// ============================================================================
+// Tests for the run history store
+// ============================================================================
+
+fn history_result(dir: &str, success: bool) -> CommandResult {
+    CommandResult {
+        success,
+        exit_code: if success { 0 } else { 1 },
+        directory: PathBuf::from(dir),
+        command: "make test".to_string(),
+        stdout: "x".repeat(history::HISTORY_OUTPUT_LIMIT + 100),
+        duration: Duration::from_millis(1500),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_history_append_list_and_show() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig::default();
+
+    let first =
+        history::append_run(temp_dir.path(), &config, &[history_result("a", true)]).unwrap();
+    let second = history::append_run(
+        temp_dir.path(),
+        &config,
+        &[history_result("a", false), history_result("b", true)],
+    )
+    .unwrap();
+    assert_eq!(first.id, 1);
+    assert_eq!(second.id, 2);
+
+    let runs = history::list_runs(temp_dir.path()).unwrap();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[1].summary.total, 2);
+    assert_eq!(runs[1].summary.failed, 1);
+
+    let shown = history::show_run(temp_dir.path(), 2).unwrap();
+    assert_eq!(shown.results.len(), 2);
+    assert_eq!(shown.results[0].duration_ms, 1500);
+    assert!(shown.results[0].stdout.len() < history::HISTORY_OUTPUT_LIMIT + 100);
+    assert!(shown.results[0].stdout.starts_with("[... truncated ...]"));
+
+    assert!(history::show_run(temp_dir.path(), 3).is_err());
+}
+
+#[test]
+fn test_history_survives_corrupt_line() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig::default();
+    history::append_run(temp_dir.path(), &config, &[history_result("a", true)]).unwrap();
+    // A run cut short while being written
+    let path = temp_dir.path().join("history.jsonl");
+    let mut content = fs::read_to_string(&path).unwrap();
+    content.push_str("{\"id\": 2, \"timest");
+    fs::write(&path, content).unwrap();
+
+    let next = history::append_run(temp_dir.path(), &config, &[history_result("b", true)]).unwrap();
+    assert_eq!(next.id, 2);
+    let runs = history::load_runs(temp_dir.path()).unwrap();
+    assert_eq!(runs.iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_history_keeps_newest_runs() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig::default();
+    for _ in 0..history::HISTORY_MAX_RUNS + 5 {
+        history::append_run(temp_dir.path(), &config, &[]).unwrap();
+    }
+    let runs = history::load_runs(temp_dir.path()).unwrap();
+    assert_eq!(runs.len(), history::HISTORY_MAX_RUNS);
+    assert_eq!(runs[0].id, 6);
+    assert_eq!(
+        runs.last().unwrap().id as usize,
+        history::HISTORY_MAX_RUNS + 5
+    );
+}
+
+#[test]
+fn test_history_compare_runs() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig::default();
+
+    history::append_run(
+        temp_dir.path(),
+        &config,
+        &[
+            history_result("a", true),
+            history_result("b", false),
+            history_result("c", true),
+        ],
+    )
+    .unwrap();
+    history::append_run(
+        temp_dir.path(),
+        &config,
+        &[
+            history_result("a", false),
+            history_result("b", true),
+            history_result("d", true),
+        ],
+    )
+    .unwrap();
+
+    let comparison = history::compare_runs(temp_dir.path(), 1, 2).unwrap();
+    let changes: Vec<_> = comparison
+        .iter()
+        .map(|c| (c.directory.as_str(), c.change))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            ("a", history::RunChange::Regressed),
+            ("b", history::RunChange::Fixed),
+            ("c", history::RunChange::Removed),
+            ("d", history::RunChange::Added),
+        ]
+    );
+    assert_eq!(comparison[0].before_exit_code, Some(0));
+    assert_eq!(comparison[0].after_exit_code, Some(1));
+}
+
+// ============================================================================
+// Tests for .env file loading
+// ============================================================================
+
+#[test]
+fn test_parse_dotenv_syntax() {
+    let content = r#"
+# comment
+export PLAIN=value # trailing comment
+SINGLE='literal $PLAIN'
+DOUBLE="line1\nline2 $PLAIN"
+BRACED=${PLAIN}-${MISSING:-fallback}
+FROM_PARENT=$PARENT_ONLY
+MULTI="first
+second"
+"#;
+    let lookup = |name: &str| (name == "PARENT_ONLY").then(|| "parent".to_string());
+    let vars: HashMap<String, String> = dotenv::parse_dotenv(content, &lookup)
+        .unwrap()
+        .into_iter()
+        .collect();
+
+    assert_eq!(vars["PLAIN"], "value");
+    assert_eq!(vars["SINGLE"], "literal $PLAIN");
+    assert_eq!(vars["DOUBLE"], "line1\nline2 value");
+    assert_eq!(vars["BRACED"], "value-fallback");
+    assert_eq!(vars["FROM_PARENT"], "parent");
+    assert_eq!(vars["MULTI"], "first\nsecond");
+}
+
+#[test]
+fn test_parse_dotenv_rejects_invalid_lines() {
+    let lookup = |_: &str| None;
+    assert!(dotenv::parse_dotenv("NOT A VARIABLE", &lookup).is_err());
+    assert!(dotenv::parse_dotenv("BAD-NAME=1", &lookup).is_err());
+    assert!(dotenv::parse_dotenv("OPEN=\"never closed", &lookup).is_err());
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_env_files_precedence() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir1 = temp_dir.path().join("dir1");
+    fs::create_dir(&dir1).unwrap();
+    fs::write(
+        temp_dir.path().join(".env"),
+        "LOOP_T_ROOT=root\nLOOP_T_DIR=root\nLOOP_T_EXTRA=root\n",
+    )
+    .unwrap();
+    fs::write(
+        dir1.join(".env"),
+        "LOOP_T_DIR=dir-${LOOP_T_ROOT}\nLOOP_T_EXTRA=dir\n",
+    )
+    .unwrap();
+
+    let config = LoopConfig {
+        silent: true,
+        load_env_files: true,
+        root_dir: Some(temp_dir.path().to_path_buf()),
+        ..Default::default()
+    };
+    let extra = HashMap::from([("LOOP_T_EXTRA".to_string(), "extra".to_string())]);
+
+    let result = execute_command_in_directory_capturing(
+        &dir1,
+        "echo $LOOP_T_ROOT/$LOOP_T_DIR/$LOOP_T_EXTRA",
+        &config,
+        &HashMap::new(),
+        Some(&extra),
+    );
+    assert!(result.success);
+    assert_eq!(result.stdout.trim(), "root/dir-root/extra");
+}
+
+#[test]
+fn test_invalid_env_file_fails_command() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join(".env"), "this is not dotenv\n").unwrap();
+
+    let config = LoopConfig {
+        silent: true,
+        load_env_files: true,
+        ..Default::default()
+    };
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "echo test",
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert!(!result.success);
+    assert!(result.stderr.contains(".env"));
+}
+
+// ============================================================================
+// Tests for clean environment mode
+// ============================================================================
+
+#[cfg(not(windows))]
+#[test]
+fn test_clean_env_drops_unlisted_variables() {
+    std::env::set_var("LOOP_T_CLEAN_SECRET", "secret");
+    std::env::set_var("LOOP_T_CLEAN_ALLOWED", "allowed");
+    let temp_dir = TempDir::new().unwrap();
+
+    let config = LoopConfig {
+        silent: true,
+        clean_env: true,
+        env_allowlist: vec!["LOOP_T_CLEAN_ALLOWED".to_string()],
+        ..Default::default()
+    };
+    let extra = HashMap::from([("LOOP_T_CLEAN_EXTRA".to_string(), "extra".to_string())]);
+
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "echo \"[$LOOP_T_CLEAN_SECRET][$LOOP_T_CLEAN_ALLOWED][$LOOP_T_CLEAN_EXTRA]\"",
+        &config,
+        &HashMap::new(),
+        Some(&extra),
+    );
+    assert!(
+        result.success,
+        "PATH should still be available: {}",
+        result.stderr
+    );
+    assert_eq!(result.stdout.trim(), "[][allowed][extra]");
+
+    // Without clean_env, the parent environment is inherited as before
+    let config = LoopConfig {
+        silent: true,
+        ..Default::default()
+    };
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "echo \"[$LOOP_T_CLEAN_SECRET]\"",
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert_eq!(result.stdout.trim(), "[secret]");
+}
+
+// ============================================================================
+// Tests for secret redaction
+// ============================================================================
+
+#[test]
+fn test_redactor_masks_env_values_and_patterns() {
+    let config = LoopConfig {
+        env: Some(HashMap::from([(
+            "LOOP_T_TOKEN".to_string(),
+            "s3cr3t-value".to_string(),
+        )])),
+        redact_env: vec!["LOOP_T_TOKEN".to_string(), "LOOP_T_UNSET".to_string()],
+        redact_patterns: vec![r"ghp_[A-Za-z0-9]+".to_string()],
+        ..Default::default()
+    };
+    let redactor = redact::Redactor::from_config(&config, None).unwrap();
+
+    assert_eq!(
+        redactor.redact("token=s3cr3t-value gh=ghp_abc123 plain"),
+        "token=[REDACTED] gh=[REDACTED] plain"
+    );
+    assert_eq!(redactor.redact("nothing here"), "nothing here");
+}
+
+#[test]
+fn test_secrets_from_env_files_are_redacted() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join(".env"),
+        "LOOP_T_TOKEN=supersecret123\n",
+    )
+    .unwrap();
+    let config = LoopConfig {
+        silent: true,
+        load_env_files: true,
+        redact_env: vec!["LOOP_T_TOKEN".to_string()],
+        ..Default::default()
+    };
+
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "echo token=supersecret123",
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert!(result.success);
+    assert_eq!(result.stdout.trim(), "token=[REDACTED]");
+    assert_eq!(result.command, "echo token=[REDACTED]");
+}
+
+#[test]
+fn test_redactor_rejects_invalid_pattern() {
+    let config = LoopConfig {
+        redact_patterns: vec!["(unclosed".to_string()],
+        ..Default::default()
+    };
+    assert!(redact::Redactor::from_config(&config, None).is_err());
+}
+
+#[test]
+fn test_captured_output_and_dry_run_are_redacted() {
+    let temp_dir = TempDir::new().unwrap();
+    let extra = HashMap::from([("LOOP_T_SECRET".to_string(), "hunter2".to_string())]);
+    let mut config = LoopConfig {
+        silent: true,
+        redact_env: vec!["LOOP_T_SECRET".to_string()],
+        ..Default::default()
+    };
+
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "echo hunter2",
+        &config,
+        &HashMap::new(),
+        Some(&extra),
+    );
+    assert!(result.success);
+    assert_eq!(result.stdout.trim(), "[REDACTED]");
+    assert_eq!(result.command, "echo [REDACTED]");
+
+    config.dry_run = true;
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "echo hunter2",
+        &config,
+        &HashMap::new(),
+        Some(&extra),
+    );
+    assert!(!result.stdout.contains("hunter2"));
+}
+
+// ============================================================================
+// Tests for bounded output capture
+// ============================================================================
+
+#[test]
+fn test_capture_stream_within_limit_keeps_everything() {
+    let data = b"short output\n".to_vec();
+    let captured = capture::capture_stream(
+        std::io::Cursor::new(data.clone()),
+        Some(1024),
+        "test",
+        &redact::Redactor::default(),
+    )
+    .unwrap();
+    assert_eq!(captured.bytes, data);
+    assert!(captured.spill.is_none());
+}
+
+#[test]
+fn test_capture_stream_spills_when_over_limit() {
+    let data: Vec<u8> = (0..50_000u32)
+        .flat_map(|i| format!("line {i}\n").into_bytes())
+        .collect();
+    let captured = capture::capture_stream(
+        std::io::Cursor::new(data.clone()),
+        Some(1000),
+        "test",
+        &redact::Redactor::default(),
+    )
+    .unwrap();
+
+    let text = String::from_utf8(captured.bytes).unwrap();
+    assert!(text.starts_with("line 0\n"));
+    assert!(text.ends_with("line 49999\n"));
+    assert!(text.contains("bytes omitted, full output in"));
+    assert!(text.len() < 1200);
+
+    let spill = captured.spill.expect("output should spill to disk");
+    assert_eq!(fs::read(&spill).unwrap(), data);
+    fs::remove_file(spill).unwrap();
+}
+
+#[test]
+fn test_capture_stream_redacts_spill_file() {
+    let config = LoopConfig {
+        redact_patterns: vec!["secret-[0-9]+".to_string()],
+        ..Default::default()
+    };
+    let redactor = redact::Redactor::from_config(&config, None).unwrap();
+    let data = "secret-123 leaked\n".repeat(200);
+
+    let captured =
+        capture::capture_stream(std::io::Cursor::new(data), Some(100), "test", &redactor).unwrap();
+    let spill = captured.spill.unwrap();
+    let spilled = fs::read_to_string(&spill).unwrap();
+    assert!(!spilled.contains("secret-123"));
+    assert_eq!(spilled.matches("[REDACTED] leaked").count(), 200);
+    fs::remove_file(spill).unwrap();
+}
+
+#[test]
+fn test_capture_stream_redacts_secrets_spanning_cuts() {
+    let config = LoopConfig {
+        redact_patterns: vec!["secret-[0-9]+".to_string()],
+        ..Default::default()
+    };
+    let redactor = redact::Redactor::from_config(&config, None).unwrap();
+    let data = format!("xxxxxxxxsecret-123\n{}\nsecret-456 end\n", "y".repeat(100));
+
+    // The head cut falls inside the first secret, the tail cut inside the last
+    let captured =
+        capture::capture_stream(std::io::Cursor::new(data), Some(20), "test", &redactor).unwrap();
+    let text = String::from_utf8(captured.bytes).unwrap();
+    assert!(text.starts_with("xxxxxxxx[R"), "{text}");
+    assert!(text.ends_with("] end\n"), "{text}");
+    assert!(!text.contains("se\n") && !text.contains("-456"), "{text}");
+    fs::remove_file(captured.spill.unwrap()).unwrap();
+}
+
+#[test]
+fn test_capture_limit_reports_spill_in_result() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        capture_limit: Some(4),
+        ..Default::default()
+    };
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "echo hello-world-output",
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert!(result.success);
+    let spill = result.stdout_spill.clone().expect("stdout should spill");
+    assert!(fs::read_to_string(&spill)
+        .unwrap()
+        .contains("hello-world-output"));
+    assert!(result.stderr_spill.is_none());
+
+    let json = JsonCommandResult::from(&result);
+    assert_eq!(json.stdout_file.as_ref(), Some(&spill));
+    fs::remove_file(spill).unwrap();
+}
+
+// ============================================================================
+// Tests for raw output bytes and encoding
+// ============================================================================
+
+#[test]
+fn test_capture_limit_keeps_utf8_output_valid() {
+    let data = "é".repeat(20);
+    let captured = capture::capture_stream(
+        std::io::Cursor::new(data.clone()),
+        Some(7),
+        "test",
+        &redact::Redactor::default(),
+    )
+    .unwrap();
+    // Both cuts fall inside a two-byte character
+    let text = String::from_utf8(captured.bytes).expect("cuts fall on char boundaries");
+    assert!(text.starts_with("é\n[... 34 bytes omitted"));
+    assert!(text.ends_with("...]\néé"));
+    fs::remove_file(captured.spill.unwrap()).unwrap();
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        capture_limit: Some(7),
+        ..Default::default()
+    };
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        &format!("printf '{data}'"),
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    let json = JsonCommandResult::from(&result);
+    assert_eq!(json.stdout_encoding, None);
+    assert!(json.stdout.starts_with("é\n"));
+    fs::remove_file(result.stdout_spill.unwrap()).unwrap();
+}
+
+#[test]
+fn test_json_result_encodes_non_utf8_output() {
+    let result = CommandResult {
+        success: true,
+        directory: PathBuf::from("/test"),
+        stdout: String::from_utf8_lossy(b"ok\xff\xfe").into_owned(),
+        stdout_bytes: b"ok\xff\xfe".to_vec(),
+        stderr: "plain\n".to_string(),
+        stderr_bytes: b"plain\n".to_vec(),
+        ..Default::default()
+    };
+
+    let json = JsonCommandResult::from(&result);
+    assert_eq!(json.stdout_encoding, Some(OutputEncoding::Base64));
+    assert_eq!(BASE64_STANDARD.decode(&json.stdout).unwrap(), b"ok\xff\xfe");
+    assert_eq!(json.stderr_encoding, None);
+    assert_eq!(json.stderr, "plain\n");
+
+    let serialized = serde_json::to_string(&json).unwrap();
+    assert!(serialized.contains("\"stdout_encoding\":\"base64\""));
+    assert!(!serialized.contains("stderr_encoding"));
+}
+
+#[test]
+fn test_redact_bytes_handles_invalid_utf8() {
+    let config = LoopConfig {
+        redact_patterns: vec!["token".to_string()],
+        ..Default::default()
+    };
+    let redactor = redact::Redactor::from_config(&config, None).unwrap();
+    assert_eq!(
+        redactor.redact_bytes(b"\xfftoken\xfe").as_ref(),
+        b"\xff[REDACTED]\xfe"
+    );
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_capturing_preserves_raw_bytes() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        ..Default::default()
+    };
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "printf 'a\\377b'",
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert!(result.success);
+    assert_eq!(result.stdout_bytes, b"a\xffb");
+    assert_eq!(result.stdout, "a\u{FFFD}b");
+}
+
+// ============================================================================
+// Tests for pseudo-terminal execution
+// ============================================================================
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_pty_mode_with_stdin() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        pty: true,
+        ..Default::default()
+    };
+    let result = execute_command_in_directory_capturing_with_stdin(
+        temp_dir.path(),
+        "if [ -t 1 ]; then echo tty; fi; exec cat",
+        &config,
+        &HashMap::new(),
+        None,
+        Some(b"piped input\n".as_slice()),
+    );
+    assert!(result.success, "{} {:?}", result.exit_code, result.stdout);
+    assert_eq!(result.stdout.replace('\r', ""), "tty\npiped input\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_pty_mode_without_stdin_reads_eof() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        pty: true,
+        ..Default::default()
+    };
+    // Would wait on the terminal forever if stdin were the PTY
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "read x; echo got:$x; cat; if [ -t 1 ]; then echo tty; fi",
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert_eq!(result.stdout, "got:\ntty\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_pty_mode_runs_command_in_terminal() {
+    let temp_dir = TempDir::new().unwrap();
+    let cmd = "if [ -t 1 ]; then echo tty; else echo pipe; fi; echo err >&2; exit 3";
+
+    let config = LoopConfig {
+        silent: true,
+        pty: true,
+        ..Default::default()
+    };
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        cmd,
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert_eq!(result.exit_code, 3);
+    assert_eq!(result.stdout, "tty\nerr\n");
+    assert!(result.stderr.is_empty());
+
+    let config = LoopConfig {
+        silent: true,
+        ..Default::default()
+    };
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        cmd,
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert_eq!(result.stdout, "pipe\n");
+    assert_eq!(result.stderr, "err\n");
+}
+
+// ============================================================================
+// Tests for stdin fan-out
+// ============================================================================
+
+#[test]
+fn test_stdin_source_load() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("input.txt");
+    fs::write(&path, "from file").unwrap();
+
+    assert_eq!(StdinSource::Text("text".into()).load().unwrap(), b"text");
+    assert_eq!(
+        StdinSource::Bytes(vec![0, 255]).load().unwrap(),
+        vec![0, 255]
+    );
+    assert_eq!(StdinSource::File(path).load().unwrap(), b"from file");
+    assert!(StdinSource::File(temp_dir.path().join("missing"))
+        .load()
+        .is_err());
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_stdin_is_fed_to_every_command() {
+    let temp_dir = TempDir::new().unwrap();
+    let dirs: Vec<PathBuf> = (0..4)
+        .map(|i| {
+            let dir = temp_dir.path().join(format!("dir{i}"));
+            fs::create_dir(&dir).unwrap();
+            dir
+        })
+        .collect();
+    let input = "line\n".repeat(20_000);
+
+    let config = LoopConfig {
+        parallel: true,
+        silent: true,
+        stdin: Some(StdinSource::Text(input.clone())),
+        ..Default::default()
+    };
+    let mut commands: Vec<DirCommand> = dirs
+        .iter()
+        .map(|dir| DirCommand {
+            dir: dir.to_str().unwrap().to_string(),
+            cmd: "cat > received.txt".to_string(),
+            ..Default::default()
+        })
+        .collect();
+    commands[3].stdin = Some(StdinSource::Text("override".to_string()));
+
+    assert!(run_commands(&config, &commands).is_ok());
+    for dir in &dirs[..3] {
+        assert_eq!(fs::read_to_string(dir.join("received.txt")).unwrap(), input);
+    }
+    assert_eq!(
+        fs::read_to_string(dirs[3].join("received.txt")).unwrap(),
+        "override"
+    );
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_captured_command_without_stdin_gets_empty_input() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        ..Default::default()
+    };
+    // Would block forever if stdin were inherited from the test harness
+    let result = execute_command_in_directory_capturing(
+        temp_dir.path(),
+        "cat",
+        &config,
+        &HashMap::new(),
+        None,
+    );
+    assert!(result.success);
+    assert!(result.stdout.is_empty());
+
+    let result = execute_command_in_directory_capturing_with_stdin(
+        temp_dir.path(),
+        "cat",
+        &config,
+        &HashMap::new(),
+        None,
+        Some(b"piped"),
+    );
+    assert_eq!(result.stdout, "piped");
+}
+
+// ============================================================================
+// Tests for interactive mode
+// ============================================================================
+
+#[test]
+fn test_interactive_choice_parse() {
+    assert_eq!(InteractiveChoice::parse("r"), Some(InteractiveChoice::Run));
+    assert_eq!(
+        InteractiveChoice::parse("yes"),
+        Some(InteractiveChoice::Run)
+    );
+    assert_eq!(InteractiveChoice::parse(""), Some(InteractiveChoice::Skip));
+    assert_eq!(InteractiveChoice::parse("a"), Some(InteractiveChoice::All));
+    assert_eq!(
+        InteractiveChoice::parse("quit"),
+        Some(InteractiveChoice::Quit)
+    );
+    assert_eq!(InteractiveChoice::parse("maybe"), None);
+}
+
+#[test]
+fn test_resolve_alias() {
+    let aliases = HashMap::from([("gst".to_string(), "git status".to_string())]);
+    assert_eq!(resolve_alias("gst -s", &aliases), "git status -s");
+    assert_eq!(resolve_alias("ls gst", &aliases), "ls gst");
+}
+
+#[test]
+fn test_skipped_result_serialization() {
+    let skipped = CommandResult {
+        success: true,
+        skipped: true,
+        directory: PathBuf::from("/test"),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&JsonCommandResult::from(&skipped)).unwrap();
+    assert!(json.contains("\"skipped\":true"));
+
+    let ran = CommandResult {
+        success: true,
+        directory: PathBuf::from("/test"),
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&JsonCommandResult::from(&ran)).unwrap();
+    assert!(!json.contains("skipped"));
+}
+
+// ============================================================================
+// Tests for dangerous-command guard rails
+// ============================================================================
+
+#[test]
+fn test_guard_default_confirm_patterns() {
+    // The default patterns are opt-in
+    let guard = CommandGuard::from_config(&LoopConfig::default()).unwrap();
+    assert_eq!(guard.check("rm -rf build"), GuardDecision::Allow);
+
+    let config = LoopConfig {
+        confirm_dangerous: true,
+        ..Default::default()
+    };
+    let guard = CommandGuard::from_config(&config).unwrap();
+    for command in [
+        "rm -rf build",
+        "rm -fr /tmp/x",
+        "rm -Rf build",
+        "rm -r -f build",
+        "rm -v --force -R build",
+        "rm --recursive --force build",
+        "git reset --hard origin/main",
+        "git push --force",
+        "git push origin main -f",
+        "git clean -fdx",
+    ] {
+        assert!(
+            matches!(guard.check(command), GuardDecision::Confirm(_)),
+            "{command} should need confirmation"
+        );
+    }
+    for command in [
+        "rm file.txt",
+        "rm -r build",
+        "rm -r foo-fr",
+        "rm -f a.txt && ls -R",
+        "git reset HEAD~1",
+        "git push",
+        "git status",
+    ] {
+        assert_eq!(guard.check(command), GuardDecision::Allow, "{command}");
+    }
+}
+
+#[test]
+fn test_guard_deny_wins_and_override() {
+    let mut config = LoopConfig {
+        deny_commands: vec![r"\bgit\s+reset\b".to_string()],
+        ..Default::default()
+    };
+    let guard = CommandGuard::from_config(&config).unwrap();
+    assert!(matches!(
+        guard.check("git reset --hard"),
+        GuardDecision::Deny(_)
+    ));
+
+    config.confirm_commands = vec![r"\bdeploy\b".to_string()];
+    config.deny_commands.clear();
+    let guard = CommandGuard::from_config(&config).unwrap();
+    assert_eq!(guard.check("rm -rf build"), GuardDecision::Allow);
+    assert!(matches!(
+        guard.check("make deploy"),
+        GuardDecision::Confirm(_)
+    ));
+
+    config.deny_commands = vec!["[".to_string()];
+    assert!(CommandGuard::from_config(&config).is_err());
+    config.allow_dangerous = true;
+    assert!(CommandGuard::from_config(&config).is_ok());
+}
+
+#[test]
+fn test_executors_refuse_guarded_commands() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("ran.txt");
+    let command = touch_cmd(&marker);
+    let aliases = HashMap::from([("zap".to_string(), command.clone())]);
+    let config = LoopConfig {
+        silent: true,
+        deny_commands: vec![regex::escape(&command)],
+        ..Default::default()
+    };
+
+    // The alias is resolved before matching
+    let result = execute_command_in_directory(temp_dir.path(), "zap", &config, &aliases, None);
+    assert!(!result.success);
+    assert!(result.stderr.contains("allow_dangerous"));
+    let result =
+        execute_command_in_directory_capturing(temp_dir.path(), "zap", &config, &aliases, None);
+    assert!(!result.success);
+    assert!(!marker.exists());
+
+    let config = LoopConfig {
+        allow_dangerous: true,
+        ..config
+    };
+    let result =
+        execute_command_in_directory_capturing(temp_dir.path(), "zap", &config, &aliases, None);
+    assert!(result.success);
+    assert!(marker.exists());
+}
+
+#[test]
+fn test_run_commands_aborts_on_denied_command() {
+    let temp_dir = TempDir::new().unwrap();
+    let other = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        deny_commands: vec!["forbidden".to_string()],
+        ..Default::default()
+    };
+    let commands = vec![
+        DirCommand {
+            dir: temp_dir.path().to_string_lossy().to_string(),
+            cmd: touch_cmd(&temp_dir.path().join("ran.txt")),
+            ..Default::default()
+        },
+        DirCommand {
+            dir: other.path().to_string_lossy().to_string(),
+            cmd: "echo forbidden".to_string(),
+            ..Default::default()
+        },
+    ];
+
+    let err = run_commands(&config, &commands).unwrap_err();
+    assert!(err.to_string().contains("deny pattern"));
+    // Nothing runs, not even the allowed command
+    assert!(!temp_dir.path().join("ran.txt").exists());
+}
+
+#[test]
+fn test_guard_checks_pipeline_steps_up_front() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("ran.txt");
+    let command = DirCommand {
+        cmd: "build".to_string(),
+        ..pipeline_in(
+            temp_dir.path(),
+            &[
+                ("prepare", touch_cmd(&marker)),
+                ("danger", "echo danger".to_string()),
+            ],
+        )
+    };
+    let config = LoopConfig {
+        silent: true,
+        deny_commands: vec!["danger".to_string()],
+        ..Default::default()
+    };
+
+    // The step matches, not the pipeline's `cmd`
+    let err = run_commands(&config, std::slice::from_ref(&command)).unwrap_err();
+    assert!(err.to_string().contains("deny pattern"));
+    assert!(!marker.exists());
+
+    // Confirm-listed steps are refused before the run without a terminal
+    let config = LoopConfig {
+        deny_commands: vec![],
+        confirm_commands: vec!["danger".to_string()],
+        ..config
+    };
+    if !io::stdin().is_terminal() {
+        let err = run_commands(&config, &[command]).unwrap_err();
+        assert!(err.to_string().contains("without confirmation"));
+        assert!(!marker.exists());
+    }
+}
+
+// ============================================================================
+// Tests for per-resource concurrency limits
+// ============================================================================
+
+#[test]
+fn test_scheduler_respects_resource_limits() {
+    let command = |resources: &[&str]| DirCommand {
+        resources: resources.iter().map(|r| r.to_string()).collect(),
+        ..Default::default()
+    };
+    let commands = vec![
+        command(&["github.com"]),
+        command(&["github.com", "github.com"]),
+        command(&["internal"]),
+        command(&["unlimited"]),
+    ];
+    let limits = HashMap::from([("github.com".to_string(), 1), ("internal".to_string(), 2)]);
+    let scheduler = Scheduler::new(&commands, &limits).unwrap();
+
+    assert_eq!(scheduler.next(), Some(0));
+    // Command 1 waits for github.com, later commands go ahead
+    assert_eq!(scheduler.next(), Some(2));
+    assert_eq!(scheduler.next(), Some(3));
+    scheduler.finish(0);
+    assert_eq!(scheduler.next(), Some(1));
+    assert_eq!(scheduler.next(), None);
+}
+
+#[test]
+fn test_scheduler_rejects_zero_limit() {
+    let limits = HashMap::from([("github.com".to_string(), 0)]);
+    assert!(Scheduler::new(&[], &limits).is_err());
+}
+
+#[test]
+fn test_parallel_run_with_resource_limits() {
+    let dirs: Vec<TempDir> = (0..4).map(|_| TempDir::new().unwrap()).collect();
+    let config = LoopConfig {
+        parallel: true,
+        silent: true,
+        resource_limits: HashMap::from([("host".to_string(), 1)]),
+        ..Default::default()
+    };
+    let commands: Vec<DirCommand> = dirs
+        .iter()
+        .map(|d| DirCommand {
+            dir: d.path().to_string_lossy().to_string(),
+            cmd: touch_cmd(&d.path().join("ran.txt")),
+            resources: vec!["host".to_string()],
+            ..Default::default()
+        })
+        .collect();
+
+    run_commands(&config, &commands).unwrap();
+    for dir in &dirs {
+        assert!(dir.path().join("ran.txt").exists());
+    }
+}
+
+// ============================================================================
+// Tests for adaptive parallelism
+// ============================================================================
+
+#[test]
+fn test_parse_proc_files() {
+    assert_eq!(
+        adaptive::parse_loadavg("2.50 1.75 1.00 3/512 12345\n"),
+        Some(2.5)
+    );
+    let meminfo =
+        "MemTotal:       16384000 kB\nMemFree:         1000000 kB\nMemAvailable:    2097152 kB\n";
+    assert_eq!(adaptive::parse_mem_available_mb(meminfo), Some(2048));
+    assert_eq!(adaptive::parse_mem_available_mb("MemTotal: 1 kB\n"), None);
+}
+
+#[test]
+fn test_adaptive_next_limit() {
+    let config = AdaptiveConfig::default();
+    let load = |load_avg, available_mb| adaptive::SystemLoad {
+        load_avg,
+        cpus: 4,
+        available_mb,
+    };
+    // Idle: ramp up, capped at the worker count
+    assert_eq!(adaptive::next_limit(4, 8, &load(1.0, 8192), &config), 5);
+    assert_eq!(adaptive::next_limit(8, 8, &load(1.0, 8192), &config), 8);
+    // Overloaded CPU: back off by one
+    assert_eq!(adaptive::next_limit(4, 8, &load(6.0, 8192), &config), 3);
+    // Low memory: halve, never below one
+    assert_eq!(adaptive::next_limit(6, 8, &load(1.0, 512), &config), 3);
+    assert_eq!(adaptive::next_limit(1, 8, &load(1.0, 512), &config), 1);
+}
+
+#[test]
+fn test_adaptive_initial_limit() {
+    let config = AdaptiveConfig::default();
+    let load = |load_avg, available_mb| adaptive::SystemLoad {
+        load_avg,
+        cpus: 4,
+        available_mb,
+    };
+    // One per CPU, capped at the worker count
+    assert_eq!(adaptive::initial_limit(8, &load(1.0, 8192), &config), 4);
+    assert_eq!(adaptive::initial_limit(2, &load(1.0, 8192), &config), 2);
+    // Pressure at the first sample lowers the starting limit
+    assert_eq!(adaptive::initial_limit(8, &load(1.0, 512), &config), 2);
+    assert_eq!(adaptive::initial_limit(8, &load(6.0, 8192), &config), 3);
+}
+
+#[test]
+fn test_scheduler_overall_limit() {
+    let commands = vec![DirCommand::default(); 3];
+    let limits = HashMap::new();
+    let scheduler = Scheduler::new(&commands, &limits).unwrap();
+    scheduler.set_limit(1);
+
+    assert_eq!(scheduler.next(), Some(0));
+    scheduler.set_limit(2);
+    assert_eq!(scheduler.next(), Some(1));
+    scheduler.finish(0);
+    assert_eq!(scheduler.next(), Some(2));
+}
+
+#[test]
+fn test_parallel_run_with_adaptive_parallelism() {
+    let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+    let config = LoopConfig {
+        parallel: true,
+        silent: true,
+        adaptive: Some(AdaptiveConfig {
+            interval_ms: 10,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let commands: Vec<DirCommand> = dirs
+        .iter()
+        .map(|d| DirCommand {
+            dir: d.path().to_string_lossy().to_string(),
+            cmd: touch_cmd(&d.path().join("ran.txt")),
+            ..Default::default()
+        })
+        .collect();
+
+    run_commands(&config, &commands).unwrap();
+    for dir in &dirs {
+        assert!(dir.path().join("ran.txt").exists());
+    }
+}
+
+// ============================================================================
+// Tests for the make jobserver
+// ============================================================================
+
+#[cfg(unix)]
+#[test]
+fn test_jobserver_tokens() {
+    let jobserver = Jobserver::create(2, Some("-k")).unwrap();
+    assert!(jobserver.makeflags().starts_with("-k -j2 "));
+    assert!(jobserver.makeflags().contains("--jobserver-auth="));
+
+    let implicit = jobserver.acquire().unwrap();
+    let token = jobserver.acquire().unwrap();
+    drop(token);
+    drop(implicit);
+    let _implicit = jobserver.acquire().unwrap();
+    let _token = jobserver.acquire().unwrap();
+
+    // A client of the same jobserver sees the descriptors we created
+    assert!(Jobserver::from_makeflags(jobserver.makeflags()).is_some());
+    assert!(Jobserver::from_makeflags("-j4 --jobserver-auth=9998,9999").is_none());
+    assert!(Jobserver::from_makeflags("-k").is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_jobserver_passed_to_commands() {
+    let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+    let config = LoopConfig {
+        parallel: true,
+        silent: true,
+        jobserver: true,
+        jobserver_slots: Some(2),
+        ..Default::default()
+    };
+    let commands: Vec<DirCommand> = dirs
+        .iter()
+        .map(|d| DirCommand {
+            dir: d.path().to_string_lossy().to_string(),
+            cmd: "echo \"$MAKEFLAGS\" > makeflags.txt".to_string(),
+            ..Default::default()
+        })
+        .collect();
+
+    run_commands(&config, &commands).unwrap();
+    for dir in &dirs {
+        let makeflags = fs::read_to_string(dir.path().join("makeflags.txt")).unwrap();
+        assert!(makeflags.contains("--jobserver-auth="), "{makeflags}");
+    }
+}
+
+// ============================================================================
+// Tests for scheduling strategies
+// ============================================================================
+
+#[test]
+fn test_schedule_order_priority_is_stable() {
+    let commands: Vec<DirCommand> = [0, 5, 0, 10, 5]
+        .iter()
+        .map(|&priority| DirCommand {
+            priority,
+            ..Default::default()
+        })
+        .collect();
+    let config = LoopConfig {
+        schedule: ScheduleStrategy::Priority,
+        ..Default::default()
+    };
+    assert_eq!(
+        schedule_order(&config, &commands).unwrap(),
+        vec![3, 1, 4, 0, 2]
+    );
+    assert_eq!(
+        schedule_order(&LoopConfig::default(), &commands).unwrap(),
+        vec![0, 1, 2, 3, 4]
+    );
+}
+
+#[test]
+fn test_schedule_order_shuffle_with_seed() {
+    let commands = vec![DirCommand::default(); 20];
+    let config = LoopConfig {
+        schedule: ScheduleStrategy::Shuffle,
+        shuffle_seed: Some(42),
+        ..Default::default()
+    };
+    let order = schedule_order(&config, &commands).unwrap();
+    assert_eq!(order, schedule_order(&config, &commands).unwrap());
+    assert_ne!(order, (0..20).collect::<Vec<_>>());
+    let mut sorted = order.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_schedule_order_longest_first() {
+    let history = TempDir::new().unwrap();
+    let config = LoopConfig {
+        history_dir: Some(history.path().to_path_buf()),
+        schedule: ScheduleStrategy::LongestFirst,
+        ..Default::default()
+    };
+    let result = |dir: &str, secs| CommandResult {
+        success: true,
+        directory: PathBuf::from(dir),
+        duration: Duration::from_secs(secs),
+        ..Default::default()
+    };
+    history::record_run(
+        history.path(),
+        &config,
+        &[result("fast", 1), result("slow", 30), result("medium", 5)],
+    )
+    .unwrap();
+
+    let commands: Vec<DirCommand> = ["fast", "medium", "new", "slow"]
+        .iter()
+        .map(|dir| DirCommand {
+            dir: dir.to_string(),
+            ..Default::default()
+        })
+        .collect();
+    // Unknown durations go first, then longest to shortest
+    assert_eq!(
+        schedule_order(&config, &commands).unwrap(),
+        vec![2, 3, 1, 0]
+    );
+}
+
+#[test]
+fn test_schedule_order_longest_first_without_history() {
+    let history = TempDir::new().unwrap();
+    // An unreadable history file
+    fs::create_dir(history.path().join("history.jsonl")).unwrap();
+    let config = LoopConfig {
+        silent: true,
+        history_dir: Some(history.path().to_path_buf()),
+        schedule: ScheduleStrategy::LongestFirst,
+        ..Default::default()
+    };
+    let commands = vec![DirCommand::default(), DirCommand::default()];
+    assert_eq!(schedule_order(&config, &commands).unwrap(), vec![0, 1]);
+}
+
+// ============================================================================
+// Tests for pipelines
+// ============================================================================
+
+fn pipeline_in(dir: &Path, steps: &[(&str, String)]) -> DirCommand {
+    DirCommand::from(&Pipeline {
+        dir: dir.to_string_lossy().to_string(),
+        steps: steps
+            .iter()
+            .map(|(name, cmd)| PipelineStep {
+                name: name.to_string(),
+                cmd: cmd.clone(),
+            })
+            .collect(),
+        env: None,
+    })
+}
+
+#[test]
+fn test_pipeline_stops_after_failed_step() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        ..Default::default()
+    };
+    let pipeline = pipeline_in(
+        temp_dir.path(),
+        &[
+            ("install", "echo installed".to_string()),
+            ("build", FAIL_CMD.to_string()),
+            ("test", touch_cmd(&temp_dir.path().join("tested.txt"))),
+        ],
+    );
+
+    let result = execute_dir_command(&pipeline, &config, &HashMap::new(), None, None, true);
+    assert!(!result.success);
+    assert_ne!(result.exit_code, 0);
+    assert!(result.stdout.contains("installed"));
+    let outcomes: Vec<_> = result
+        .steps
+        .iter()
+        .map(|s| (s.name.as_str(), s.success, s.skipped))
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            ("install", true, false),
+            ("build", false, false),
+            ("test", false, true)
+        ]
+    );
+    assert!(!temp_dir.path().join("tested.txt").exists());
+
+    let json = serde_json::to_string(&JsonCommandResult::from(&result)).unwrap();
+    assert!(json.contains("\"name\":\"build\""));
+}
+
+#[test]
+fn test_pipeline_continue_on_step_failure() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        continue_on_step_failure: true,
+        ..Default::default()
+    };
+    let pipeline = pipeline_in(
+        temp_dir.path(),
+        &[
+            ("build", FAIL_CMD.to_string()),
+            ("test", touch_cmd(&temp_dir.path().join("tested.txt"))),
+        ],
+    );
+
+    let result = execute_dir_command(&pipeline, &config, &HashMap::new(), None, None, false);
+    assert!(!result.success);
+    assert!(result.steps.iter().all(|s| !s.skipped));
+    assert!(temp_dir.path().join("tested.txt").exists());
+}
+
+#[test]
+fn test_run_pipelines_in_parallel() {
+    let dirs: Vec<TempDir> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+    let config = LoopConfig {
+        parallel: true,
+        silent: true,
+        ..Default::default()
+    };
+    let pipelines: Vec<Pipeline> = dirs
+        .iter()
+        .map(|d| Pipeline {
+            dir: d.path().to_string_lossy().to_string(),
+            steps: vec![
+                PipelineStep {
+                    name: "first".to_string(),
+                    cmd: touch_cmd(&d.path().join("first.txt")),
+                },
+                PipelineStep {
+                    name: "second".to_string(),
+                    cmd: touch_cmd(&d.path().join("second.txt")),
+                },
+            ],
+            env: None,
+        })
+        .collect();
+
+    run_pipelines(&config, &pipelines).unwrap();
+    for dir in &dirs {
+        assert!(dir.path().join("first.txt").exists());
+        assert!(dir.path().join("second.txt").exists());
+    }
+}
+
+// ============================================================================
+// Tests for matrix execution
+// ============================================================================
+
+#[test]
+fn test_expand_matrix() {
+    let commands = vec![DirCommand {
+        dir: "repo".to_string(),
+        cmd: "cargo test".to_string(),
+        env: Some(HashMap::from([
+            ("RUST_LOG".to_string(), "warn".to_string()),
+            ("KEEP".to_string(), "1".to_string()),
+        ])),
+        ..Default::default()
+    }];
+    let axes = vec![
+        matrix::MatrixAxis::env_var("log", "RUST_LOG", &["debug", "info"]),
+        matrix::MatrixAxis::env_var("features", "FEATURES", &["default", "all"]),
+    ];
+
+    let expanded = matrix::expand_matrix(&commands, &axes);
+    let labels: Vec<_> = expanded
+        .iter()
+        .map(|c| c.variant.clone().unwrap())
+        .collect();
+    assert_eq!(
+        labels,
+        vec![
+            "log=debug, features=default",
+            "log=debug, features=all",
+            "log=info, features=default",
+            "log=info, features=all",
+        ]
+    );
+    let env = expanded[3].env.as_ref().unwrap();
+    assert_eq!(env["RUST_LOG"], "info");
+    assert_eq!(env["FEATURES"], "all");
+    assert_eq!(env["KEEP"], "1");
+
+    assert_eq!(matrix::expand_matrix(&commands, &[]).len(), 1);
+}
+
+#[test]
+fn test_render_matrix_grid() {
+    let result = |dir: &str, variant: &str, success| CommandResult {
+        success,
+        directory: PathBuf::from(dir),
+        variant: Some(variant.to_string()),
+        ..Default::default()
+    };
+    let grid = matrix::render_grid(&[
+        result("/ws/api", "log=debug", true),
+        result("/ws/api", "log=info", false),
+        result("/ws/web-client", "log=debug", true),
+    ]);
+    assert_eq!(
+        grid,
+        "            log=debug  log=info\n\
+         api         ✓          ✗\n\
+         web-client  ✓          -\n"
+    );
+}
+
+#[test]
+fn test_run_matrix_sets_variant_env() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        ..Default::default()
+    };
+    #[cfg(windows)]
+    let cmd = "echo %LEVEL%> level-%LEVEL%.txt";
+    #[cfg(not(windows))]
+    let cmd = "echo $LEVEL > level-$LEVEL.txt";
+    let commands = vec![DirCommand {
+        dir: temp_dir.path().to_string_lossy().to_string(),
+        cmd: cmd.to_string(),
+        ..Default::default()
+    }];
+    let axes = vec![matrix::MatrixAxis::env_var(
+        "level",
+        "LEVEL",
+        &["low", "high"],
+    )];
+
+    run_matrix(&config, &commands, &axes).unwrap();
+    assert!(temp_dir.path().join("level-low.txt").exists());
+    assert!(temp_dir.path().join("level-high.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rerun_failed_matrix_only_runs_failed_variants() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = LoopConfig {
+        silent: true,
+        history_dir: Some(temp_dir.path().join("history")),
+        ..Default::default()
+    };
+    let command = |cmd: &str| {
+        vec![DirCommand {
+            dir: temp_dir.path().to_string_lossy().to_string(),
+            cmd: cmd.to_string(),
+            ..Default::default()
+        }]
+    };
+    let axes = vec![matrix::MatrixAxis::env_var(
+        "level",
+        "LEVEL",
+        &["low", "high"],
+    )];
+    assert!(run_matrix(&config, &command("test $LEVEL = low"), &axes).is_err());
+
+    config.rerun_failed = true;
+    run_matrix(&config, &command("touch rerun-$LEVEL.txt"), &axes).unwrap();
+    assert!(!temp_dir.path().join("rerun-low.txt").exists());
+    assert!(temp_dir.path().join("rerun-high.txt").exists());
+}
+
+// ============================================================================
+// Tests for watch mode
+// ============================================================================
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_watcher_reports_changed_roots() {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    fs::create_dir_all(first.path().join("src")).unwrap();
+    fs::create_dir_all(first.path().join("target")).unwrap();
+    let roots = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+    let mut watcher = watch::inotify::Watcher::new(&roots, &["target".to_string()]).unwrap();
+
+    // Ignored paths don't count as changes
+    fs::write(first.path().join("target").join("out.o"), "x").unwrap();
+    fs::write(first.path().join("src").join("main.rs"), "fn main() {}").unwrap();
+    let changed = watcher.wait_for_changes(Duration::from_millis(50)).unwrap();
+    assert_eq!(changed, vec![first.path().to_path_buf()]);
+
+    // A new subdirectory is watched too
+    fs::create_dir(second.path().join("new")).unwrap();
+    watcher.wait_for_changes(Duration::from_millis(50)).unwrap();
+    fs::write(second.path().join("new").join("file.txt"), "x").unwrap();
+    let changed = watcher.wait_for_changes(Duration::from_millis(50)).unwrap();
+    assert_eq!(changed, vec![second.path().to_path_buf()]);
+
+    // Edits made while no one was waiting (during a run) are still reported
+    fs::write(first.path().join("src").join("main.rs"), "fn main() { }").unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    let changed = watcher.wait_for_changes(Duration::from_millis(50)).unwrap();
+    assert_eq!(changed, vec![first.path().to_path_buf()]);
+}
+
+#[test]
+fn test_watch_ignore_only_affects_watching() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("target")).unwrap();
+    let config = LoopConfig {
+        watch_ignore: vec!["target".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        watch::watch_exclusions(&config),
+        vec![".git".to_string(), "target".to_string()]
+    );
+    let dirs = expand_directories(
+        &[temp_dir.path().to_string_lossy().to_string()],
+        &config.ignore,
+    )
+    .unwrap();
+    assert_eq!(dirs.len(), 2);
+}
+
+#[test]
+fn test_watch_cycle_summary() {
+    let results = vec![
+        CommandResult {
+            success: true,
+            ..Default::default()
+        },
+        CommandResult {
+            success: false,
+            ..Default::default()
+        },
+    ];
+    assert_eq!(
+        watch::cycle_summary(2, &results),
+        "[watch] Cycle 2: 2 ran, 1 passed, 1 failed"
+    );
+}
+
+// ============================================================================
+// Tests for the result cache
+// ============================================================================
+
+#[test]
+fn test_cache_skips_unchanged_directories() {
+    let work = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    fs::write(work.path().join("input.txt"), "one").unwrap();
+    let runs = state.path().join("runs.txt");
+    let config = LoopConfig {
+        silent: true,
+        cache: true,
+        history_dir: Some(state.path().join(".loop")),
+        ..Default::default()
+    };
+    let commands = vec![DirCommand {
+        dir: work.path().to_string_lossy().to_string(),
+        cmd: format!("echo ran>> \"{}\"", runs.display()),
+        ..Default::default()
+    }];
+    let run_count = || fs::read_to_string(&runs).unwrap().lines().count();
+
+    let results = execute_and_report(&config, &commands).unwrap();
+    assert!(results[0].success && !results[0].cached);
+    assert_eq!(run_count(), 1);
+
+    let results = execute_and_report(&config, &commands).unwrap();
+    assert!(results[0].success && results[0].cached);
+    assert_eq!(run_count(), 1);
+
+    // Changing a file invalidates the entry
+    fs::write(work.path().join("input.txt"), "two").unwrap();
+    let results = execute_and_report(&config, &commands).unwrap();
+    assert!(!results[0].cached);
+    assert_eq!(run_count(), 2);
+
+    // So does changing the command's env
+    let commands = vec![DirCommand {
+        env: Some(HashMap::from([("MODE".to_string(), "release".to_string())])),
+        ..commands[0].clone()
+    }];
+    let results = execute_and_report(&config, &commands).unwrap();
+    assert!(!results[0].cached);
+    assert_eq!(run_count(), 3);
+}
+
+#[test]
+fn test_cache_key_covers_env_files_and_clean_env() {
+    let root = TempDir::new().unwrap();
+    let work = root.path().join("work");
+    fs::create_dir_all(&work).unwrap();
+    fs::write(root.path().join(".env"), "MODE=debug\n").unwrap();
+    let mut config = LoopConfig {
+        root_dir: Some(root.path().to_path_buf()),
+        load_env_files: true,
+        ..Default::default()
+    };
+    let key = |config: &LoopConfig| {
+        let env = child_env(&work, config, None).unwrap();
+        cache::cache_key(&work, "make", &env, None, config).unwrap()
+    };
+
+    let debug = key(&config);
+    assert_eq!(key(&config), debug);
+
+    // A changed workspace .env changes the key
+    fs::write(root.path().join(".env"), "MODE=release\n").unwrap();
+    let release = key(&config);
+    assert_ne!(release, debug);
+
+    // So do clean_env and its allowlist
+    config.clean_env = true;
+    let clean = key(&config);
+    assert_ne!(clean, release);
+    config.env_allowlist = vec!["LOOP_TEST_CACHE_VAR".to_string()];
+    assert_ne!(key(&config), clean);
+}
+
+#[test]
+fn test_cache_does_not_store_failures() {
+    let work = TempDir::new().unwrap();
+    let config = LoopConfig {
+        silent: true,
+        cache: true,
+        history_dir: Some(work.path().join(".loop")),
+        ..Default::default()
+    };
+    let commands = vec![DirCommand {
+        dir: work.path().to_string_lossy().to_string(),
+        cmd: FAIL_CMD.to_string(),
+        ..Default::default()
+    }];
+    for _ in 0..2 {
+        let results = execute_and_report(&config, &commands).unwrap();
+        assert!(!results[0].success && !results[0].cached);
+    }
+}
+
+#[test]
+fn test_input_fingerprint() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    fs::create_dir_all(dir.path().join("target")).unwrap();
+    let ignore = vec!["target".to_string()];
+    let before = cache::input_fingerprint(dir.path(), &ignore).unwrap();
+
+    // Ignored paths and loop's state directory don't count
+    fs::write(dir.path().join("target").join("out.o"), "x").unwrap();
+    fs::create_dir_all(dir.path().join(".loop")).unwrap();
+    fs::write(dir.path().join(".loop").join("cache.json"), "{}").unwrap();
+    assert_eq!(
+        cache::input_fingerprint(dir.path(), &ignore).unwrap(),
+        before
+    );
+
+    fs::write(dir.path().join("b.txt"), "b").unwrap();
+    assert_ne!(
+        cache::input_fingerprint(dir.path(), &ignore).unwrap(),
+        before
+    );
+}
+
+#[test]
+fn test_input_fingerprint_uses_git_tree_when_clean() {
+    let dir = TempDir::new().unwrap();
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(args)
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    };
+    if !git(&["init", "-q"]) {
+        // git isn't installed
+        return;
+    }
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    assert!(git(&["add", "a.txt"]));
+    assert!(git(&[
+        "-c",
+        "user.name=test",
+        "-c",
+        "user.email=test@example.com",
+        "commit",
+        "-q",
+        "-m",
+        "init"
+    ]));
+    let clean = cache::input_fingerprint(dir.path(), &[]).unwrap();
+    assert!(clean.starts_with("tree:"), "{clean}");
+
+    fs::write(dir.path().join("a.txt"), "changed").unwrap();
+    let dirty = cache::input_fingerprint(dir.path(), &[]).unwrap();
+    assert!(dirty.starts_with("files:"), "{dirty}");
+}
+
+// ============================================================================
+// Tests for grouped output
+// ============================================================================
+
+#[test]
+fn test_group_outputs_with_normalization() {
+    let result = |dir: &str, stdout: &str, success| CommandResult {
+        success,
+        directory: PathBuf::from(dir),
+        stdout: stdout.to_string(),
+        ..Default::default()
+    };
+    let results = vec![
+        result("/ws/a", "main\n", true),
+        result("/ws/b", "develop\n", true),
+        result("/ws/c", "main  \n\n", true),
+        result("/ws/d", "main (took 12ms)\n", true),
+        result("/ws/e", "", false),
+    ];
+    let normalize = vec![r" \(took \d+ms\)".to_string()];
+    let groups = group::group_outputs(&results, &normalize).unwrap();
+    assert_eq!(
+        group::render_groups(&groups),
+        "✓ 3 directories: a, c, d\n    main\n\n\
+         ✓ 1 directory: b (outlier)\n    develop\n\n\
+         ✗ 1 directory: e (outlier)\n    (no output)\n\n"
+    );
+
+    assert!(group::group_outputs(&results, &["(".to_string()]).is_err());
+}
+
+#[test]
+fn test_parallel_run_with_grouped_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let commands: Vec<DirCommand> = ["one", "two"]
+        .iter()
+        .map(|name| {
+            let dir = temp_dir.path().join(name);
+            fs::create_dir(&dir).unwrap();
+            DirCommand {
+                dir: dir.to_string_lossy().to_string(),
+                cmd: "echo same".to_string(),
+                ..Default::default()
+            }
+        })
+        .collect();
+    let config = LoopConfig {
+        parallel: true,
+        group_output: true,
+        ..Default::default()
+    };
+    let results = execute_and_report(&config, &commands).unwrap();
+    assert!(results.iter().all(|r| r.stdout.trim() == "same"));
+}
+
+// ============================================================================
+// Tests for baseline diffing
+// ============================================================================
+
+#[test]
+fn test_unified_diff() {
+    let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+    let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\n";
+    assert_eq!(
+        baseline::unified_diff(old, new, "base", "other"),
+        "--- base\n+++ other\n\
+         @@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n\
+         @@ -8,3 +8,4 @@\n h\n i\n j\n+k\n"
+    );
+    assert_eq!(baseline::unified_diff(old, old, "base", "other"), "");
+    assert_eq!(
+        baseline::unified_diff("", "x\n", "base", "other"),
+        "--- base\n+++ other\n@@ -0,0 +1,1 @@\n+x\n"
+    );
+}
+
+#[test]
+fn test_colored_baseline_diff_uses_line_kinds() {
+    use colored::Colorize;
+
+    let result = |dir: &str, stdout: &str| CommandResult {
+        success: true,
+        directory: PathBuf::from(dir),
+        stdout: stdout.to_string(),
+        ..Default::default()
+    };
+    // Content that looks like file headers is still a removed/added line
+    let results = vec![result("/ws/app", "-- old\n"), result("/ws/lib", "++ new\n")];
+    let diff = baseline::render_baseline_diffs_colored(&results, Path::new("/ws/app"));
+    let lines: Vec<&str> = diff.lines().collect();
+    assert_eq!(lines.len(), 5, "{diff}");
+    assert_eq!(lines[3], "--- old".red().to_string());
+    assert_eq!(lines[4], "+++ new".green().to_string());
+}
+
+#[test]
+fn test_baseline_diffs() {
+    let commands: Vec<DirCommand> = ["/ws/app", "/ws/lib", "/other/lib"]
+        .iter()
+        .map(|dir| DirCommand {
+            dir: dir.to_string(),
+            ..Default::default()
+        })
+        .collect();
+    assert_eq!(
+        baseline::resolve_baseline("app", &commands).unwrap(),
+        PathBuf::from("/ws/app")
+    );
+    assert_eq!(
+        baseline::resolve_baseline("/ws/lib", &commands).unwrap(),
+        PathBuf::from("/ws/lib")
+    );
+    assert!(baseline::resolve_baseline("lib", &commands).is_err());
+    assert!(baseline::resolve_baseline("missing", &commands).is_err());
+
+    let result = |dir: &str, stdout: &str| CommandResult {
+        success: true,
+        directory: PathBuf::from(dir),
+        stdout: stdout.to_string(),
+        ..Default::default()
+    };
+    let results = vec![
+        result("/ws/app", "serde 1.0\n"),
+        result("/ws/lib", "serde 1.0\n"),
+        result("/other/lib", "serde 0.9\n"),
+    ];
+    let base = Path::new("/ws/app");
+    let expected = format!(
+        "= {}: identical to baseline\n--- {}\n+++ {}\n@@ -1,1 +1,1 @@\n-serde 1.0\n+serde 0.9\n",
+        Path::new("/ws/lib").display(),
+        base.display(),
+        Path::new("/other/lib").display()
+    );
+    assert_eq!(baseline::render_baseline_diffs(&results, base), expected);
+}
+
+// ============================================================================
+// Tests for the table summary
+// ============================================================================
+
+#[test]
+fn test_render_table() {
+    let results = vec![
+        CommandResult {
+            success: true,
+            directory: PathBuf::from("/ws/app"),
+            stdout: "\n\x1b[32mv1.2.3\x1b[0m\nmore\n".to_string(),
+            duration: Duration::from_millis(1200),
+            ..Default::default()
+        },
+        CommandResult {
+            success: false,
+            exit_code: 2,
+            directory: PathBuf::from("/ws/library"),
+            stderr: "error: something went badly wrong\n".to_string(),
+            duration: Duration::from_millis(300),
+            ..Default::default()
+        },
+        CommandResult {
+            success: true,
+            directory: PathBuf::from("/ws/docs"),
+            skipped: true,
+            ..Default::default()
+        },
+    ];
+    assert_eq!(
+        table::render_table(&results, None, None, false),
+        "DIRECTORY  STATUS   EXIT  DURATION  OUTPUT\n\
+         app        ok       0     1.2s      v1.2.3\n\
+         library    FAIL     2     0.3s      error: something went badly wrong\n\
+         docs       skipped  -     0.0s\n\
+         3 commands: 1 passed, 1 failed, 1 skipped\n"
+    );
+
+    // Width-limited rows cut the output column
+    let table = table::render_table(&results, None, Some(52), false);
+    assert!(table.contains("  error: somethin…\n"), "{table}");
+
+    let extract = regex::Regex::new(r"v(\d+)\.").unwrap();
+    let table = table::render_table(&results[..1], Some(&extract), None, false);
+    assert!(table.contains("1.2s      1\n"), "{table}");
+}
+
+#[test]
+fn test_sequential_run_with_table_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = LoopConfig {
+        table_output: true,
+        ..Default::default()
+    };
+    let commands = vec![DirCommand {
+        dir: temp_dir.path().to_string_lossy().to_string(),
+        cmd: "echo captured".to_string(),
+        ..Default::default()
+    }];
+    // Output is captured for the excerpt
+    let results = execute_and_report(&config, &commands).unwrap();
+    assert_eq!(results[0].stdout.trim(), "captured");
+
+    let config = LoopConfig {
+        table_extract: Some("(".to_string()),
+        ..config
+    };
+    assert!(execute_and_report(&config, &commands).is_err());
+}
+
+// ============================================================================
+// Tests for report files
+// ============================================================================
+
+#[test]
+fn test_junit_xml() {
+    let results = vec![
+        CommandResult {
+            success: true,
+            directory: PathBuf::from("/ws/app"),
+            stdout: "ok <done>\n".to_string(),
+            duration: Duration::from_millis(1500),
+            ..Default::default()
+        },
+        CommandResult {
+            success: false,
+            exit_code: 3,
+            directory: PathBuf::from("/ws/lib"),
+            stderr: "\x1b[31merror\x1b[0m: \"bad\" & worse\n".to_string(),
+            duration: Duration::from_millis(250),
+            variant: Some("os=linux".to_string()),
+            ..Default::default()
+        },
+        CommandResult {
+            success: true,
+            directory: PathBuf::from("/ws/docs"),
+            skipped: true,
+            ..Default::default()
+        },
+    ];
+    let dir = |path: &str| PathBuf::from(path).display().to_string();
+    let expected = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="loop" tests="3" failures="1" errors="0" skipped="1" time="1.750">
+  <testsuite name="loop" tests="3" failures="1" errors="0" skipped="1" time="1.750">
+    <testcase name="{}" classname="loop" time="1.500">
+      <system-out>ok &lt;done&gt;
+</system-out>
+    </testcase>
+    <testcase name="{} [os=linux]" classname="loop" time="0.250">
+      <failure message="Exit code 3" type="exit code 3">error: &quot;bad&quot; &amp; worse
+</failure>
+      <system-err>error: &quot;bad&quot; &amp; worse
+</system-err>
+    </testcase>
+    <testcase name="{}" classname="loop" time="0.000">
+      <skipped/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#,
+        dir("/ws/app"),
+        dir("/ws/lib"),
+        dir("/ws/docs")
+    );
+    assert_eq!(report::junit_xml(&results), expected);
+}
+
+#[test]
+fn test_run_writes_junit_report() {
+    let temp_dir = TempDir::new().unwrap();
+    let report_path = temp_dir.path().join("junit.xml");
+    let config = LoopConfig {
+        silent: true,
+        junit_report: Some(report_path.clone()),
+        ..Default::default()
+    };
+    let commands = vec![
+        DirCommand {
+            dir: temp_dir.path().to_string_lossy().to_string(),
+            cmd: "echo hello".to_string(),
+            ..Default::default()
+        },
+        DirCommand {
+            dir: temp_dir.path().to_string_lossy().to_string(),
+            cmd: FAIL_CMD.to_string(),
+            ..Default::default()
+        },
+    ];
+    assert!(run_commands(&config, &commands).is_err());
+
+    let xml = fs::read_to_string(&report_path).unwrap();
+    assert!(xml.contains(r#"tests="2" failures="1""#), "{xml}");
+    assert!(xml.contains("<system-out>hello"), "{xml}");
+}
+
+#[test]
+fn test_stdout_report_rejected_with_json_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("ran.txt");
+    let config = LoopConfig {
+        silent: true,
+        json_output: true,
+        tap_report: Some(PathBuf::from("-")),
+        ..Default::default()
+    };
+    let commands = vec![DirCommand {
+        dir: temp_dir.path().to_string_lossy().to_string(),
+        cmd: touch_cmd(&marker),
+        ..Default::default()
+    }];
+    let err = run_commands(&config, &commands).unwrap_err();
+    assert!(err.to_string().contains("JSON output"), "{err}");
+    assert!(!marker.exists());
+}
+
+#[test]
+fn test_stdout_report_implies_silent_and_is_unique() {
+    let config = LoopConfig {
+        junit_report: Some(PathBuf::from("-")),
+        html_report: Some(PathBuf::from("report.html")),
+        ..Default::default()
+    };
+    assert!(stdout_report_config(&config).unwrap().silent);
+    assert!(!stdout_report_config(&LoopConfig::default()).unwrap().silent);
+
+    let config = LoopConfig {
+        markdown_report: Some(PathBuf::from("-")),
+        ..config
+    };
+    let err = stdout_report_config(&config).unwrap_err();
+    assert!(err.to_string().contains("Only one report"), "{err}");
+    assert!(run_commands(&config, &[]).is_err());
+}
+
+#[test]
+fn test_tap_and_markdown_reports() {
+    let results = vec![
+        CommandResult {
+            success: true,
+            directory: PathBuf::from("/ws/app"),
+            stdout: "fine\n".to_string(),
+            duration: Duration::from_millis(1500),
+            ..Default::default()
+        },
+        CommandResult {
+            success: false,
+            exit_code: 1,
+            directory: PathBuf::from("/ws/lib"),
+            stdout: "compiling\n".to_string(),
+            stderr: "error: ```broken```\n".to_string(),
+            duration: Duration::from_millis(250),
+            ..Default::default()
+        },
+        CommandResult {
+            success: true,
+            directory: PathBuf::from("/ws/docs"),
+            skipped: true,
+            ..Default::default()
+        },
+    ];
+    let dir = |path: &str| PathBuf::from(path).display().to_string();
+
+    assert_eq!(
+        report::tap(&results),
+        format!(
+            "TAP version 13\n1..3\nok 1 - {}\nnot ok 2 - {}\n  ---\n  exit_code: 1\n  \
+             duration_ms: 250\n  stdout: |\n    compiling\n  stderr: |\n    \
+             error: ```broken```\n  ...\nok 3 - {} # SKIP skipped\n",
+            dir("/ws/app"),
+            dir("/ws/lib"),
+            dir("/ws/docs")
+        )
+    );
+
+    assert_eq!(
+        report::markdown(&results),
+        format!(
+            "## loop: 3 commands: 1 passed, 1 failed, 1 skipped\n\n\
+             | Directory | Status | Exit code | Duration |\n\
+             | --- | --- | --- | --- |\n\
+             | `{app}` | ✅ passed | 0 | 1.5s |\n\
+             | `{lib}` | ❌ failed | 1 | 0.2s |\n\
+             | `{docs}` | ⏭️ skipped | - | 0.0s |\n\
+             \n### Failures\n\
+             \n<details>\n<summary><code>{lib}</code>: exit code 1</summary>\n\n\
+             ````\ncompiling\nerror: ```broken```\n````\n\
+             \n</details>\n",
+            app = dir("/ws/app"),
+            lib = dir("/ws/lib"),
+            docs = dir("/ws/docs")
+        )
+    );
+}
+
+#[test]
+fn test_run_writes_tap_and_markdown_reports() {
+    let temp_dir = TempDir::new().unwrap();
+    let tap_path = temp_dir.path().join("run.tap");
+    let markdown_path = temp_dir.path().join("run.md");
+    let config = LoopConfig {
+        silent: true,
+        tap_report: Some(tap_path.clone()),
+        markdown_report: Some(markdown_path.clone()),
+        ..Default::default()
+    };
+    let commands = vec![DirCommand {
+        dir: temp_dir.path().to_string_lossy().to_string(),
+        cmd: "echo hello".to_string(),
+        ..Default::default()
+    }];
+    run_commands(&config, &commands).unwrap();
+
+    assert!(fs::read_to_string(&tap_path)
+        .unwrap()
+        .starts_with("TAP version 13\n1..1\nok 1 - "));
+    assert!(fs::read_to_string(&markdown_path)
+        .unwrap()
+        .starts_with("## loop: 1 commands: 1 passed, 0 failed\n"));
+}
+
+#[test]
+fn test_ansi_to_html() {
+    assert_eq!(
+        report::ansi_to_html("\x1b[1;31merror\x1b[0m: a < b\x1b[K\n"),
+        "<span style=\"color:#cd3131;font-weight:bold\">error</span>: a &lt; b\n"
+    );
+    assert_eq!(
+        report::ansi_to_html("\x1b[38;5;196mred\x1b[39;48;2;0;128;255mblue bg\x1b[m"),
+        "<span style=\"color:#ff0000\">red</span>\
+         <span style=\"background-color:#0080ff\">blue bg</span>"
+    );
+}
+
+#[test]
+fn test_html_report() {
+    let results = vec![
+        CommandResult {
+            success: true,
+            directory: PathBuf::from("/ws/app"),
+            command: "make <all>".to_string(),
+            stdout: "\x1b[32mbuilt\x1b[0m\n".to_string(),
+            ..Default::default()
+        },
+        CommandResult {
+            success: false,
+            exit_code: 2,
+            directory: PathBuf::from("/ws/lib"),
+            stderr: "boom\n".to_string(),
+            ..Default::default()
+        },
+    ];
+    let html = report::html(&results);
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("1 passed, 1 failed"));
+    assert!(html.contains("<code>$ make &lt;all&gt;</code>"));
+    assert!(html.contains("<span style=\"color:#0dbc79\">built</span>"));
+    assert!(html.contains(&format!(
+        "<details class=\"result\" data-status=\"failed\" data-name=\"{}\" open>",
+        PathBuf::from("/ws/lib").display()
+    )));
+    assert!(html.contains("exit code 2"));
+    assert!(html.trim_end().ends_with("</html>"));
+}
+
+#[test]
+fn test_run_writes_html_report() {
+    let temp_dir = TempDir::new().unwrap();
+    let report_path = temp_dir.path().join("run.html");
+    let config = LoopConfig {
+        silent: true,
+        html_report: Some(report_path.clone()),
+        ..Default::default()
+    };
+    let commands = vec![DirCommand {
+        dir: temp_dir.path().to_string_lossy().to_string(),
+        cmd: "echo hello".to_string(),
+        ..Default::default()
+    }];
+    run_commands(&config, &commands).unwrap();
+
+    let html = fs::read_to_string(&report_path).unwrap();
+    assert!(html.contains("<pre class=\"stdout\">hello"), "{html}");
+}
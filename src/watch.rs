@@ -0,0 +1,277 @@
+//! Watch mode: rerun commands in the directories whose files change.
+//! Uses inotify, so it is only available on Linux.
+
+use crate::{CommandResult, DirCommand, LoopConfig};
+use anyhow::Result;
+
+/// Runs `commands`, then waits for file changes and reruns the commands of
+/// the changed directories, forever. Changes are debounced by
+/// `watch_debounce_ms`; paths matching [`watch_exclusions`] and loop's state
+/// directory are not watched. Changes made while commands run are kept and
+/// rerun in the next cycle, so output the commands write inside the
+/// directories (e.g. `target`) should be listed in `watch_ignore`.
+#[cfg(target_os = "linux")]
+pub(crate) fn watch_commands(config: &LoopConfig, commands: &[DirCommand]) -> Result<()> {
+    use colored::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for command in commands {
+        let dir = PathBuf::from(&command.dir);
+        if !roots.contains(&dir) {
+            roots.push(dir);
+        }
+    }
+    let mut watcher = inotify::Watcher::new(&roots, &watch_exclusions(config))?;
+    let debounce = Duration::from_millis(config.watch_debounce_ms);
+    let report = !config.silent && !config.json_output;
+
+    let mut selected = commands.to_vec();
+    let mut cycle = 0;
+    loop {
+        cycle += 1;
+        let results = crate::execute_and_report(config, &selected)?;
+        if report {
+            println!("{}", cycle_summary(cycle, &results).bold());
+            println!("{} Waiting for changes...", "[watch]".cyan());
+        }
+
+        let changed = watcher.wait_for_changes(debounce)?;
+        if report {
+            let names: Vec<String> = changed
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect();
+            println!(
+                "\n{} Changed: {}",
+                "[watch]".cyan(),
+                names.join(", ").yellow()
+            );
+        }
+        selected = commands
+            .iter()
+            .filter(|c| changed.contains(&PathBuf::from(&c.dir)))
+            .cloned()
+            .collect();
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn watch_commands(_config: &LoopConfig, _commands: &[DirCommand]) -> Result<()> {
+    anyhow::bail!("Watch mode is only supported on Linux")
+}
+
+/// Paths excluded from watching: `ignore` followed by `watch_ignore`.
+pub(crate) fn watch_exclusions(config: &LoopConfig) -> Vec<String> {
+    config
+        .ignore
+        .iter()
+        .chain(&config.watch_ignore)
+        .cloned()
+        .collect()
+}
+
+/// One-line summary of a watch cycle.
+pub(crate) fn cycle_summary(cycle: usize, results: &[CommandResult]) -> String {
+    let failed = results.iter().filter(|r| !r.success).count();
+    let skipped = results.iter().filter(|r| r.skipped).count();
+    let passed = results.len() - failed - skipped;
+    let mut summary = format!(
+        "[watch] Cycle {cycle}: {} ran, {passed} passed, {failed} failed",
+        results.len()
+    );
+    if skipped > 0 {
+        summary.push_str(&format!(", {skipped} skipped"));
+    }
+    summary
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) mod inotify {
+    use crate::{history, should_ignore};
+    use anyhow::{Context, Result};
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr};
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    const EVENT_MASK: u32 = libc::IN_CLOSE_WRITE
+        | libc::IN_MODIFY
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO;
+
+    /// Recursive inotify watches over a set of root directories. A
+    /// subdirectory that is itself a root belongs to that root only.
+    pub(crate) struct Watcher {
+        fd: File,
+        roots: Vec<PathBuf>,
+        ignore: Vec<String>,
+        /// Watch descriptor to (root index, watched directory).
+        watches: HashMap<i32, (usize, PathBuf)>,
+    }
+
+    impl Watcher {
+        pub fn new(roots: &[PathBuf], ignore: &[String]) -> Result<Self> {
+            // SAFETY: inotify_init1 returns a new descriptor or -1
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+            if fd == -1 {
+                return Err(io::Error::last_os_error()).context("Failed to initialize inotify");
+            }
+            let mut watcher = Watcher {
+                // SAFETY: the descriptor was just created and is owned by us
+                fd: unsafe { File::from_raw_fd(fd) },
+                roots: roots.to_vec(),
+                ignore: ignore.to_vec(),
+                watches: HashMap::new(),
+            };
+            for (index, root) in roots.iter().enumerate() {
+                watcher
+                    .add_tree(index, root)
+                    .with_context(|| format!("Failed to watch {}", root.display()))?;
+            }
+            Ok(watcher)
+        }
+
+        fn is_excluded(&self, path: &Path) -> bool {
+            path.file_name() == Some(OsStr::new(history::STATE_DIR_NAME))
+                || should_ignore(path, &self.ignore)
+        }
+
+        /// Watches `dir` and its subdirectories for the root at `index`.
+        fn add_tree(&mut self, index: usize, dir: &Path) -> io::Result<()> {
+            let path = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            // SAFETY: path is a valid NUL-terminated string
+            let wd =
+                unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), EVENT_MASK) };
+            if wd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            self.watches.insert(wd, (index, dir.to_path_buf()));
+
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir()
+                    && !path.is_symlink()
+                    && !self.is_excluded(&path)
+                    && !self.roots.contains(&path)
+                {
+                    // Directories may vanish while we walk
+                    if let Err(e) = self.add_tree(index, &path) {
+                        if e.kind() != io::ErrorKind::NotFound {
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Blocks until a watched file changes, then until no further change
+        /// arrives for `debounce`. Returns the changed roots in root order.
+        pub fn wait_for_changes(&mut self, debounce: Duration) -> Result<Vec<PathBuf>> {
+            let mut changed = vec![false; self.roots.len()];
+            let mut timeout = None;
+            loop {
+                match self.read_events(timeout)? {
+                    // Quiet for the debounce period
+                    None if timeout.is_some() => break,
+                    None => {}
+                    Some(batch) => {
+                        if !batch.is_empty() {
+                            timeout = Some(debounce);
+                        }
+                        for index in batch {
+                            changed[index] = true;
+                        }
+                    }
+                }
+            }
+            Ok(self
+                .roots
+                .iter()
+                .zip(changed)
+                .filter(|(_, changed)| *changed)
+                .map(|(root, _)| root.clone())
+                .collect())
+        }
+
+        /// Waits up to `timeout` (forever for `None`) for events. Returns
+        /// `None` if none arrived, otherwise the root index of each relevant
+        /// (not excluded) event read.
+        fn read_events(&mut self, timeout: Option<Duration>) -> Result<Option<Vec<usize>>> {
+            let mut pollfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+            // SAFETY: polls a single descriptor we own
+            if unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(None);
+                }
+                return Err(err).context("Failed to wait for file changes");
+            }
+
+            let mut buf = [0u8; 64 * 1024];
+            let len = match self.fd.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e).context("Failed to read file change events"),
+            };
+
+            let header = std::mem::size_of::<libc::inotify_event>();
+            let mut indices = Vec::new();
+            let mut offset = 0;
+            while offset + header <= len {
+                // SAFETY: the kernel writes whole events; the header may be
+                // unaligned within our byte buffer
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr().cast()) };
+                let name_bytes = &buf[offset + header..offset + header + event.len as usize];
+                let name_len = name_bytes
+                    .iter()
+                    .position(|&b| b == 0)
+                    .unwrap_or(name_bytes.len());
+                let name = OsStr::from_bytes(&name_bytes[..name_len]);
+                offset += header + event.len as usize;
+
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    // Events were lost; assume every root changed
+                    indices.extend(0..self.roots.len());
+                    continue;
+                }
+                if event.mask & libc::IN_IGNORED != 0 {
+                    self.watches.remove(&event.wd);
+                    continue;
+                }
+                let Some((index, dir)) = self.watches.get(&event.wd).cloned() else {
+                    continue;
+                };
+                let path = dir.join(name);
+                if self.is_excluded(&path) {
+                    continue;
+                }
+                if event.mask & libc::IN_ISDIR != 0
+                    && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0
+                    && !self.roots.contains(&path)
+                {
+                    // A new directory's own watch may miss early changes;
+                    // it counts as a change itself
+                    let _ = self.add_tree(index, &path);
+                }
+                indices.push(index);
+            }
+            Ok(Some(indices))
+        }
+    }
+}